@@ -7,8 +7,6 @@ var audioSystem = {
     }
 }
 
-var block = ["r/gtafk","r/bi_irl", "r/suddenlybi", "r/ennnnnnnnnnnnbbbbbby", "r/feemagers", "r/BrexitAteMyFace", "r/emoney", "r/Inzaghi"];
-
 function stateIsDark(state) {
     // The darkStates array is included in the html directly via server side rendering and updated via status message.
     return darkStates.includes(state);
@@ -103,6 +101,23 @@ function handleStateUpdate(message) {
         }
     }
 
+    // A filtered page was rendered by the server, so only update the tiles it shows.
+    if (serverFiltered) {
+        for (subreddit of message["subreddits"]) {
+            var tile = document.getElementById(subreddit["name"]);
+            if (tile != null) {
+                var state = mapState(subreddit["state"]);
+                tile.querySelector("p").innerHTML = state;
+                for (i of Object.values(stateMap)) {
+                    tile.classList.remove(`subreddit-${i}`);
+                }
+                tile.classList.add(`subreddit-${state}`);
+            }
+        }
+        updateStatusText();
+        return;
+    }
+
     var newHtml = "";
     for (section of message["sections"]) {
         newHtml += `<h1>${section}</h1>\n`;
@@ -110,7 +125,24 @@ function handleStateUpdate(message) {
         for (subreddit of message["subreddits"]) {
             if (subreddit["section"] === section) {
                 var s = mapState(subreddit["state"]);
-                newHtml += `<div class="subreddit subreddit-${s}" id="${subreddit["name"]}">\n`;
+                var tooltip = "";
+                if (subreddit["subscribers"]) {
+                    tooltip = (subreddit["title"] ? subreddit["title"] + " - " : "") + subreddit["subscribers"] + " subscribers";
+                    if (subreddit["active_users"]) {
+                        tooltip += ", " + subreddit["active_users"] + " online";
+                    }
+                }
+                if (subreddit["pin_reason"]) {
+                    tooltip += "\nSet by admin: " + subreddit["pin_reason"];
+                }
+                for (note of subreddit["notes"] || []) {
+                    tooltip += "\nNote: " + note;
+                }
+                if (subreddit["schedule"] && subreddit["schedule"].length > 0) {
+                    tooltip += "\nDark on: " + subreddit["schedule"].join(", ");
+                }
+                var pinned = subreddit["pin_reason"] ? " subreddit-pinned" : "";
+                newHtml += `<div class="subreddit subreddit-${s}${pinned}" id="${subreddit["name"]}" title="${tooltip.trim().replace(/"/g, "&quot;")}">\n`;
                 newHtml += `<a href="https://old.reddit.com/${subreddit.name}" target="_blank" rel="noopener noreferrer">${subreddit.name}</a>\n`;
                 newHtml += `<p>${s}</p>\n`;
                 newHtml += `</div>\n`;
@@ -124,10 +156,6 @@ function handleStateUpdate(message) {
 }
 
 function handleDeltaUpdate(message) {
-    if (message["name"] in block || block.includes(message["name"])) {
-        return;
-    }
-
     // True if swapped between PRIVATE and RESTRICTED, false if not
     var swapped = stateIsDark(message["state"]) && stateIsDark(message["previous_state"]);
     var text = `<strong>${message["name"]}</strong> ${swapped ? 'swapped to' : 'has gone'} ${mapState(message["state"])}! (${message["section"]})`;
@@ -180,7 +208,7 @@ function handleDeltaUpdate(message) {
 var eventSource = newEventSource();
 
 function newEventSource() {
-    var eventSource = new EventSource('sse');
+    var eventSource = new EventSource(basePath + 'sse');
 
     eventSource.onopen = function (event) {
         console.log("Server connection open!");
@@ -0,0 +1,65 @@
+// Live updates for the alternative views. Keeps counters, progress bars and subreddit tiles current.
+// Elements opt in with data-dark, data-total, data-percent, data-togo and data-progress attributes.
+
+function updateCounters(dark, total) {
+    var percent = total > 0 ? (dark / total * 100).toFixed(2) : "0.00";
+    document.querySelectorAll("[data-dark]").forEach(e => e.textContent = dark);
+    document.querySelectorAll("[data-total]").forEach(e => e.textContent = total);
+    document.querySelectorAll("[data-togo]").forEach(e => e.textContent = total - dark);
+    document.querySelectorAll("[data-percent]").forEach(e => e.textContent = percent);
+    document.querySelectorAll("[data-progress]").forEach(e => e.style.width = percent + "%");
+}
+
+function updateTile(name, state) {
+    var tile = document.getElementById(name);
+    if (tile == null) {
+        return;
+    }
+    var mapped = stateMap[state];
+    for (var s of Object.values(stateMap)) {
+        tile.classList.remove(`subreddit-${s}`);
+    }
+    tile.classList.add(`subreddit-${mapped}`);
+    var label = tile.querySelector("p");
+    if (label != null) {
+        label.textContent = mapped;
+    }
+}
+
+function newViewEventSource() {
+    var eventSource = new EventSource(basePath + 'sse');
+
+    eventSource.onerror = function (event) {
+        eventSource.close();
+        setTimeout(newViewEventSource, 3000);
+    }
+
+    eventSource.onmessage = function (event) {
+        const message = JSON.parse(event.data);
+        switch (message.type) {
+            case "CurrentStateUpdate":
+                var content = message["content"];
+                stateMap = content["state_map"];
+                var dark = content["subreddits"].filter(s => content["dark_states"].includes(s["state"])).length;
+                updateCounters(dark, content["subreddits"].length);
+                for (var subreddit of content["subreddits"]) {
+                    updateTile(subreddit["name"], subreddit["state"]);
+                }
+                break;
+            case "Delta":
+                updateTile(message["content"]["name"], message["content"]["state"]);
+                break;
+            case "Reload":
+                setTimeout(() => {
+                    window.location.reload();
+                }, Math.floor(Math.random() * 20000));
+                break;
+            default:
+                break;
+        }
+    }
+
+    return eventSource;
+}
+
+newViewEventSource();
@@ -0,0 +1,279 @@
+use std::str::FromStr;
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Subcommand;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::Cli;
+use crate::reddit::{Subreddit, SubredditDelta, SubredditState};
+use crate::reddit::id::SubredditId;
+use crate::redis_helper::RedisHelper;
+
+/// A state an admin pinned on a subreddit. It wins over everything the updater finds, until it expires.
+#[derive(Clone, Debug, Serialize, Deserialize, Ord, PartialOrd, PartialEq, Eq)]
+pub struct StatePin {
+    pub state: SubredditState,
+    pub reason: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl StatePin {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map(|t| t <= Utc::now()).unwrap_or(false)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Ord, PartialOrd, PartialEq, Eq)]
+pub struct SubredditNote {
+    pub text: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What admins attached to a subreddit. Kept across list and state updates.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Ord, PartialOrd, PartialEq, Eq)]
+pub struct SubredditAnnotations {
+    #[serde(default)]
+    pub pin: Option<StatePin>,
+    #[serde(default)]
+    pub notes: Vec<SubredditNote>,
+    /// Days the subreddit announced it will be dark.
+    #[serde(default)]
+    pub schedule: Vec<NaiveDate>,
+}
+
+/// A change to the blocklist, kept as an audit log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockAuditEntry {
+    pub subreddit: SubredditId,
+    pub blocked: bool,
+    pub reason: Option<String>,
+    pub author: String,
+    pub at: DateTime<Utc>,
+}
+
+/// An admin API token, as `name:token`. The name is recorded as the author of everything done with the token.
+#[derive(Clone, Debug)]
+pub struct AdminToken {
+    pub name: String,
+    pub token: String,
+}
+
+impl FromStr for AdminToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, token) = s.split_once(':').ok_or_else(|| anyhow::anyhow!("Admin token must be name:token"))?;
+        if name.is_empty() || token.is_empty() {
+            return Err(anyhow::anyhow!("Admin token must be name:token"));
+        }
+        Ok(AdminToken { name: name.to_string(), token: token.to_string() })
+    }
+}
+
+/// Loads a tracked subreddit to change.
+pub async fn load(redis_helper: &RedisHelper, id: &SubredditId) -> Result<Subreddit> {
+    redis_helper.get_subreddit(id).await?
+        .ok_or_else(|| anyhow::anyhow!("Subreddit {id} is not tracked"))
+}
+
+/// How often a change is tried again on a freshly loaded subreddit when it keeps being changed by someone else.
+const MAX_ATTEMPTS: usize = 5;
+
+/// Applies `change` to the subreddit and stores it. If it was changed since it was loaded, the change is applied
+/// again to a fresh copy, so nothing written in the meantime is lost.
+async fn modify(redis_helper: &RedisHelper, mut sub: Subreddit, change: impl Fn(&mut SubredditDelta)) -> Result<Subreddit> {
+    for _ in 0..MAX_ATTEMPTS {
+        let mut delta = SubredditDelta::from(sub.clone());
+        change(&mut delta);
+        if redis_helper.apply_delta(&delta).await? {
+            delta.subreddit.version += 1;
+            return Ok(delta.subreddit);
+        }
+        sub = load(redis_helper, &sub.id()).await?;
+    }
+    Err(anyhow::anyhow!("Subreddit {} keeps changing, try again", sub.name))
+}
+
+/// Pins `state` on a subreddit, applying it right away.
+pub async fn pin_state(redis_helper: &RedisHelper, sub: Subreddit, state: SubredditState, reason: &str, expires_at: Option<DateTime<Utc>>, author: &str) -> Result<Subreddit> {
+    info!("{author} pinned {} to {state:?}: {reason}", sub.name);
+    let pin = StatePin {
+        state,
+        reason: reason.to_string(),
+        author: author.to_string(),
+        created_at: Utc::now(),
+        expires_at,
+    };
+    modify(redis_helper, sub, |delta| {
+        delta.subreddit.annotations.pin = Some(pin.clone());
+        delta.subreddit.state = state;
+        delta.actor = Some(author.to_string());
+        delta.reason = Some(reason.to_string());
+    }).await
+}
+
+/// Removes a pin. The subreddit keeps its state until the updater next looks at it.
+pub async fn unpin_state(redis_helper: &RedisHelper, sub: Subreddit, author: &str) -> Result<Subreddit> {
+    info!("{author} unpinned {}", sub.name);
+    modify(redis_helper, sub, |delta| delta.subreddit.annotations.pin = None).await
+}
+
+pub async fn add_note(redis_helper: &RedisHelper, sub: Subreddit, text: &str, author: &str) -> Result<Subreddit> {
+    info!("{author} added a note to {}", sub.name);
+    let note = SubredditNote {
+        text: text.to_string(),
+        author: author.to_string(),
+        created_at: Utc::now(),
+    };
+    modify(redis_helper, sub, |delta| delta.subreddit.annotations.notes.push(note.clone())).await
+}
+
+pub async fn clear_notes(redis_helper: &RedisHelper, sub: Subreddit, author: &str) -> Result<Subreddit> {
+    info!("{author} cleared the notes of {}", sub.name);
+    modify(redis_helper, sub, |delta| delta.subreddit.annotations.notes.clear()).await
+}
+
+/// Replaces the blackout schedule of a subreddit.
+pub async fn set_schedule(redis_helper: &RedisHelper, sub: Subreddit, mut dates: Vec<NaiveDate>, author: &str) -> Result<Subreddit> {
+    info!("{author} set the blackout schedule of {} to {dates:?}", sub.name);
+    dates.sort();
+    dates.dedup();
+    modify(redis_helper, sub, |delta| delta.subreddit.annotations.schedule = dates.clone()).await
+}
+
+/// Subreddits the page used to hide in its script, before the blocklist moved server-side.
+const OLD_PAGE_BLOCKLIST: [&str; 8] = ["gtafk", "bi_irl", "suddenlybi", "ennnnnnnnnnnnbbbbbby", "feemagers", "BrexitAteMyFace", "emoney", "Inzaghi"];
+
+/// Blocks what the page used to hide, once per tracker, so unblocking one of them later sticks.
+pub async fn seed_blocklist(redis_helper: &RedisHelper) -> Result<()> {
+    if redis_helper.is_migrated("blocklist_seed").await? {
+        return Ok(());
+    }
+    info!("Blocking the subreddits the page used to hide...");
+    for name in OLD_PAGE_BLOCKLIST {
+        set_blocked(redis_helper, &SubredditId::new(name), true, Some("Hidden by the page before the blocklist moved server-side"), "migration").await?;
+    }
+    redis_helper.set_migrated("blocklist_seed").await
+}
+
+/// Blocks or unblocks a subreddit, logging who did it. Subreddits don't need to be tracked to be blocked.
+pub async fn set_blocked(redis_helper: &RedisHelper, id: &SubredditId, blocked: bool, reason: Option<&str>, author: &str) -> Result<bool> {
+    let changed = redis_helper.set_blocked(id, blocked).await?;
+    if changed {
+        info!("{author} {} {id}", if blocked { "blocked" } else { "unblocked" });
+        redis_helper.push_block_audit(&BlockAuditEntry {
+            subreddit: id.clone(),
+            blocked,
+            reason: reason.map(|r| r.to_string()),
+            author: author.to_string(),
+            at: Utc::now(),
+        }).await?;
+    }
+    Ok(changed)
+}
+
+#[derive(Subcommand)]
+pub enum AdminAction {
+    /// Pin a state on a subreddit, overriding what the updater finds
+    Pin {
+        #[clap(long = "subreddit", short = 's')]
+        subreddit: SubredditId,
+        #[clap(long = "state")]
+        state: SubredditState,
+        #[clap(long = "reason")]
+        reason: String,
+        /// Hours until the pin expires. Without it, the pin stays until removed.
+        #[clap(long = "expires-in")]
+        expires_in: Option<i64>,
+    },
+    /// Remove a pinned state
+    Unpin {
+        #[clap(long = "subreddit", short = 's')]
+        subreddit: SubredditId,
+    },
+    /// Add a note to a subreddit
+    Note {
+        #[clap(long = "subreddit", short = 's')]
+        subreddit: SubredditId,
+        #[clap(long = "text")]
+        text: String,
+    },
+    /// Remove every note from a subreddit
+    ClearNotes {
+        #[clap(long = "subreddit", short = 's')]
+        subreddit: SubredditId,
+    },
+    /// Set the days a subreddit announced it will be dark, replacing earlier ones
+    Schedule {
+        #[clap(long = "subreddit", short = 's')]
+        subreddit: SubredditId,
+        /// A day, as YYYY-MM-DD. Can be repeated. Leave out to clear the schedule.
+        #[clap(long = "date")]
+        dates: Vec<NaiveDate>,
+    },
+    /// Hide subreddits from the list, the page and the API
+    Block {
+        /// Can be repeated.
+        #[clap(long = "subreddit", short = 's', required = true)]
+        subreddits: Vec<SubredditId>,
+        #[clap(long = "reason")]
+        reason: Option<String>,
+    },
+    /// Show blocked subreddits again
+    Unblock {
+        /// Can be repeated.
+        #[clap(long = "subreddit", short = 's', required = true)]
+        subreddits: Vec<SubredditId>,
+    },
+    /// Show the blocklist and its latest changes
+    Blocklist {
+        #[clap(long = "count", default_value = "20")]
+        count: isize,
+    },
+}
+
+pub async fn admin(cli: &Cli, author: &str, action: &AdminAction) -> Result<()> {
+    let redis_helper = RedisHelper::new(cli).await?;
+    let sub = match action {
+        AdminAction::Block { subreddits, reason } => {
+            for id in subreddits {
+                if !set_blocked(&redis_helper, id, true, reason.as_deref(), author).await? {
+                    info!("{id} was already blocked.");
+                }
+            }
+            return Ok(());
+        }
+        AdminAction::Unblock { subreddits } => {
+            for id in subreddits {
+                if !set_blocked(&redis_helper, id, false, None, author).await? {
+                    info!("{id} was not blocked.");
+                }
+            }
+            return Ok(());
+        }
+        AdminAction::Blocklist { count } => {
+            let mut blocked = redis_helper.get_blocklist().await?.into_iter().collect::<Vec<SubredditId>>();
+            blocked.sort();
+            info!("Blocked: {}", blocked.iter().join(", "));
+            for entry in redis_helper.get_block_audit(*count).await? {
+                info!("{} {} {} {}{}", entry.at, entry.author, if entry.blocked { "blocked" } else { "unblocked" }, entry.subreddit,
+                    entry.reason.map(|r| format!(": {r}")).unwrap_or_default());
+            }
+            return Ok(());
+        }
+        AdminAction::Pin { subreddit, state, reason, expires_in } => {
+            let expires_at = expires_in.map(|h| Utc::now() + chrono::Duration::hours(h));
+            pin_state(&redis_helper, load(&redis_helper, subreddit).await?, *state, reason, expires_at, author).await?
+        }
+        AdminAction::Unpin { subreddit } => unpin_state(&redis_helper, load(&redis_helper, subreddit).await?, author).await?,
+        AdminAction::Note { subreddit, text } => add_note(&redis_helper, load(&redis_helper, subreddit).await?, text, author).await?,
+        AdminAction::ClearNotes { subreddit } => clear_notes(&redis_helper, load(&redis_helper, subreddit).await?, author).await?,
+        AdminAction::Schedule { subreddit, dates } => set_schedule(&redis_helper, load(&redis_helper, subreddit).await?, dates.clone(), author).await?,
+    };
+    info!("Subreddit {} is now {:?}, annotations: {:?}", sub.name, sub.state, sub.annotations);
+    Ok(())
+}
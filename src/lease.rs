@@ -0,0 +1,83 @@
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use tracing::{info, warn};
+use crate::redis_helper::RedisHelper;
+
+/// Held by the updater and the list updater while they read and write subreddits, so their runs don't overlap.
+pub const UPDATE_LEASE: &str = "update";
+
+/// How long a lease lasts without renewal. A holder that dies blocks the others for at most this long.
+const LEASE_TTL: Duration = Duration::from_secs(60);
+const RENEW_INTERVAL: Duration = Duration::from_secs(20);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An exclusive hold on `name` within a tracker, kept in Redis so it holds across processes and machines.
+pub struct Lease {
+    redis_helper: RedisHelper,
+    name: String,
+    token: String,
+    /// When the lease was last taken or renewed, as of sending the request. It lasts `LEASE_TTL` from then.
+    renewed_at: Instant,
+}
+
+impl Lease {
+    /// Waits until nobody else holds `name`, then takes it.
+    pub async fn acquire(redis_helper: &RedisHelper, name: &str) -> Result<Lease> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let token = format!("{}:{nanos}", std::process::id());
+        let mut waiting = false;
+        let mut renewed_at = Instant::now();
+        while !redis_helper.try_lock(name, &token, LEASE_TTL).await? {
+            if !waiting {
+                info!("Another process holds the {name} lease, waiting for it...");
+                waiting = true;
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+            renewed_at = Instant::now();
+        }
+        Ok(Lease {
+            redis_helper: redis_helper.clone(),
+            name: name.to_string(),
+            token,
+            renewed_at,
+        })
+    }
+
+    /// Runs `f`, renewing the lease meanwhile, then releases it. If the lease is lost, `f` is dropped, so any tasks it
+    /// starts should be tied to it, e.g. in a `JoinSet`, to stop with it.
+    pub async fn hold<T>(self, f: impl Future<Output=Result<T>>) -> Result<T> {
+        let result = tokio::select! {
+            result = f => result,
+            e = self.keep_alive() => Err(e),
+        };
+        if let Err(e) = self.redis_helper.release_lock(&self.name, &self.token).await {
+            warn!("Unable to release the {} lease, it will expire by itself: {e}", self.name);
+        }
+        result
+    }
+
+    /// Renews the lease until it is lost. A failed renewal is retried for as long as the lease lasts.
+    async fn keep_alive(&self) -> anyhow::Error {
+        let mut renewed_at = self.renewed_at;
+        let mut wait = RENEW_INTERVAL;
+        loop {
+            tokio::time::sleep(wait).await;
+            let attempted_at = Instant::now();
+            match self.redis_helper.renew_lock(&self.name, &self.token, LEASE_TTL).await {
+                Ok(true) => {
+                    renewed_at = attempted_at;
+                    wait = RENEW_INTERVAL;
+                }
+                Ok(false) => return anyhow::anyhow!("Lost the {} lease", self.name),
+                Err(e) if renewed_at.elapsed() + RETRY_INTERVAL >= LEASE_TTL => {
+                    return e.context(format!("Unable to renew the {} lease before it expired", self.name));
+                }
+                Err(e) => {
+                    warn!("Unable to renew the {} lease, retrying: {e}", self.name);
+                    wait = RETRY_INTERVAL;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use crate::list_source::{ListSource, new_subreddit, parse_list_document};
+use crate::reddit::{Reddit, Subreddit};
+
+/// Reads the list from a local CSV, JSON or YAML file, picked by extension.
+/// CSV files have a `name` column and an optional `section` column.
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf) -> Self {
+        FileSource {
+            path,
+        }
+    }
+}
+
+fn parse_csv(text: &str, origin: &str) -> (Vec<String>, Vec<Subreddit>) {
+    let mut sections: Vec<String> = Vec::new();
+    let mut subreddits = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let mut cols = line.split(',').map(|c| c.trim().trim_matches('"'));
+        let name = cols.next().unwrap_or("");
+        if name.is_empty() || (idx == 0 && name.eq_ignore_ascii_case("name")) {
+            continue;
+        }
+        let sub = new_subreddit(name, cols.next(), origin);
+        if !sections.contains(&sub.section) {
+            sections.push(sub.section.clone());
+        }
+        subreddits.push(sub);
+    }
+    (sections, subreddits)
+}
+
+#[async_trait]
+impl ListSource for FileSource {
+    fn origin(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    async fn fetch(&self, _reddit: &Reddit) -> Result<(Vec<String>, Vec<Subreddit>)> {
+        let text = tokio::fs::read_to_string(&self.path).await
+            .with_context(|| format!("Unable to read {}", self.path.display()))?;
+        let extension = self.path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "csv" | "txt" => Ok(parse_csv(&text, &self.origin())),
+            "json" => parse_list_document(serde_json::from_str(&text)?, &self.origin()),
+            "yaml" | "yml" => parse_list_document(serde_yaml::from_str::<Value>(&text)?, &self.origin()),
+            _ => Err(anyhow::anyhow!("Unknown list file type: {}", self.path.display())),
+        }
+    }
+}
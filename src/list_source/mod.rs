@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::reddit::{Reddit, Subreddit, SubredditState};
+use crate::reddit::id::SubredditId;
+
+pub mod file;
+pub mod multireddit;
+pub mod url;
+pub mod wiki;
+
+/// Section used for entries whose source does not group them.
+pub const DEFAULT_SECTION: &str = "Other";
+
+/// A place to get a list of subreddits, grouped into sections, from.
+#[async_trait]
+pub trait ListSource: Sync + Send {
+    /// Describes where the list comes from. Stored on every subreddit it yields.
+    fn origin(&self) -> String;
+
+    async fn fetch(&self, reddit: &Reddit) -> Result<(Vec<String>, Vec<Subreddit>)>;
+}
+
+/// A list source as given on the command line:
+/// `wiki:<subreddit>/<page>`, `file:<path>`, `url:<url>` or `multi:<user>/<multireddit>`.
+#[derive(Clone, Debug)]
+pub enum ListSourceSpec {
+    Wiki { subreddit: String, page: String },
+    File(PathBuf),
+    Url(String),
+    Multireddit { user: String, name: String },
+}
+
+impl FromStr for ListSourceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, arg) = s.split_once(':').ok_or_else(|| anyhow::anyhow!("List source must be <kind>:<argument>: {s}"))?;
+        match kind {
+            "wiki" => {
+                let arg = arg.trim_start_matches("r/");
+                let (subreddit, page) = arg.split_once('/').unwrap_or((arg, "index"));
+                Ok(ListSourceSpec::Wiki { subreddit: subreddit.to_string(), page: page.to_string() })
+            }
+            "file" => Ok(ListSourceSpec::File(PathBuf::from(arg))),
+            "url" => Ok(ListSourceSpec::Url(arg.to_string())),
+            "multi" => {
+                let (user, name) = arg.trim_start_matches("u/").split_once('/')
+                    .ok_or_else(|| anyhow::anyhow!("Multireddit source must be multi:<user>/<name>: {s}"))?;
+                Ok(ListSourceSpec::Multireddit { user: user.to_string(), name: name.to_string() })
+            }
+            _ => Err(anyhow::anyhow!("Unknown list source kind: {kind}")),
+        }
+    }
+}
+
+impl ListSourceSpec {
+    /// `client` is shared by every source that fetches over HTTP, so they reuse its connections.
+    pub fn into_source(self, client: &reqwest::Client) -> Box<dyn ListSource> {
+        match self {
+            ListSourceSpec::Wiki { subreddit, page } => Box::new(wiki::WikiSource::new(&subreddit, &page)),
+            ListSourceSpec::File(path) => Box::new(file::FileSource::new(path)),
+            ListSourceSpec::Url(url) => Box::new(url::UrlSource::new(&url, client.clone(), Duration::from_secs(30))),
+            ListSourceSpec::Multireddit { user, name } => Box::new(multireddit::MultiredditSource::new(&user, &name)),
+        }
+    }
+}
+
+/// Normalises a subreddit name to the `r/Name` form used as `Subreddit::name`, keeping its case for display.
+pub fn subreddit_name(name: &str) -> String {
+    let name = name.trim().trim_matches('/');
+    let name = name.strip_prefix("r/").unwrap_or(name);
+    format!("r/{name}")
+}
+
+/// Builds a subreddit entry tagged with the given origin.
+pub fn new_subreddit(name: &str, section: Option<&str>, origin: &str) -> Subreddit {
+    Subreddit {
+        name: subreddit_name(name),
+        section: section.filter(|s| !s.is_empty()).unwrap_or(DEFAULT_SECTION).to_string(),
+        state: SubredditState::UNKNOWN,
+        source: origin.to_string(),
+        withdrawn_at: None,
+        fullname: None,
+        baseline: None,
+        detected: None,
+        metadata: Default::default(),
+        annotations: Default::default(),
+        version: 0,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListEntry {
+    Name(String),
+    Entry { name: String, section: Option<String> },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListDocument {
+    List(Vec<ListEntry>),
+    Sections(serde_json::Map<String, Value>),
+}
+
+/// Reads a structured list, in any of these shapes:
+/// a list of names, a list of `{name, section}` objects, or a map of section to list of names.
+pub fn parse_list_document(value: Value, origin: &str) -> Result<(Vec<String>, Vec<Subreddit>)> {
+    let doc: ListDocument = serde_json::from_value(value)?;
+    let mut sections: Vec<String> = Vec::new();
+    let mut subreddits = Vec::new();
+    let mut add = |name: &str, section: Option<&str>| {
+        let sub = new_subreddit(name, section, origin);
+        if !sections.contains(&sub.section) {
+            sections.push(sub.section.clone());
+        }
+        subreddits.push(sub);
+    };
+    match doc {
+        ListDocument::List(entries) => {
+            for entry in entries {
+                match entry {
+                    ListEntry::Name(name) => add(&name, None),
+                    ListEntry::Entry { name, section } => add(&name, section.as_deref()),
+                }
+            }
+        }
+        ListDocument::Sections(map) => {
+            for (section, names) in map {
+                let names: Vec<String> = serde_json::from_value(names)?;
+                for name in names {
+                    add(&name, Some(&section));
+                }
+            }
+        }
+    }
+    Ok((sections, subreddits))
+}
+
+/// Fetches every source and merges the results.
+/// Sections keep the order they first appear in, and the first source to list a subreddit wins.
+pub async fn fetch_all(sources: &[Box<dyn ListSource>], reddit: &Reddit) -> Result<(Vec<String>, Vec<Subreddit>)> {
+    let mut sections: Vec<String> = Vec::new();
+    let mut subreddits: Vec<Subreddit> = Vec::new();
+    let mut seen: HashSet<SubredditId> = HashSet::new();
+    for source in sources {
+        let (src_sections, src_subreddits) = source.fetch(reddit).await
+            .map_err(|e| e.context(format!("Unable to fetch list from {}", source.origin())))?;
+        for section in src_sections {
+            if !sections.contains(&section) {
+                sections.push(section);
+            }
+        }
+        for sub in src_subreddits {
+            if seen.insert(sub.id()) {
+                subreddits.push(sub);
+            }
+        }
+    }
+    Ok((sections, subreddits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wiki(spec: &str) -> (String, String) {
+        match spec.parse::<ListSourceSpec>().unwrap() {
+            ListSourceSpec::Wiki { subreddit, page } => (subreddit, page),
+            other => panic!("Not a wiki source: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_wiki_specs() {
+        assert_eq!(wiki("wiki:ModCoord"), ("ModCoord".to_string(), "index".to_string()));
+        assert_eq!(wiki("wiki:r/ModCoord"), ("ModCoord".to_string(), "index".to_string()));
+        assert_eq!(wiki("wiki:r/ModCoord/list"), ("ModCoord".to_string(), "list".to_string()));
+    }
+}
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use crate::list_source::{DEFAULT_SECTION, ListSource, new_subreddit};
+use crate::reddit::{Reddit, Subreddit};
+
+/// Reads the list from the subreddits in a user's multireddit. Everything goes in one section.
+pub struct MultiredditSource {
+    user: String,
+    name: String,
+}
+
+impl MultiredditSource {
+    pub fn new(user: &str, name: &str) -> Self {
+        MultiredditSource {
+            user: user.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ListSource for MultiredditSource {
+    fn origin(&self) -> String {
+        format!("multi:{}/{}", self.user, self.name)
+    }
+
+    async fn fetch(&self, reddit: &Reddit) -> Result<(Vec<String>, Vec<Subreddit>)> {
+        let names = reddit.fetch_multireddit(&self.user, &self.name).await?;
+        let subreddits = names.iter()
+            .map(|n| new_subreddit(n, None, &self.origin()))
+            .collect::<Vec<Subreddit>>();
+        let sections = if subreddits.is_empty() { vec![] } else { vec![DEFAULT_SECTION.to_string()] };
+        Ok((sections, subreddits))
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use crate::list_source::{ListSource, parse_list_document};
+use crate::reddit::{Reddit, Subreddit};
+
+/// Reads the list from a JSON document at a URL, in any shape `parse_list_document` accepts.
+pub struct UrlSource {
+    url: String,
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+impl UrlSource {
+    pub fn new(url: &str, client: reqwest::Client, timeout: Duration) -> Self {
+        UrlSource {
+            url: url.to_string(),
+            client,
+            timeout,
+        }
+    }
+}
+
+#[async_trait]
+impl ListSource for UrlSource {
+    fn origin(&self) -> String {
+        format!("url:{}", self.url)
+    }
+
+    async fn fetch(&self, _reddit: &Reddit) -> Result<(Vec<String>, Vec<Subreddit>)> {
+        let resp = self.client.get(&self.url).timeout(self.timeout).send().await?.error_for_status()?;
+        let data: Value = resp.json().await?;
+        parse_list_document(data, &self.origin())
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::{info, warn};
+use crate::list_source::{ListSource, new_subreddit};
+use crate::reddit::{Reddit, Subreddit};
+use crate::reddit::id::SubredditId;
+
+/// Reads the list from a subreddit wiki page, using its headings as sections.
+pub struct WikiSource {
+    subreddit: String,
+    page: String,
+}
+
+impl WikiSource {
+    pub fn new(subreddit: &str, page: &str) -> Self {
+        WikiSource {
+            subreddit: subreddit.to_string(),
+            page: page.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ListSource for WikiSource {
+    fn origin(&self) -> String {
+        format!("wiki:{}/{}", self.subreddit, self.page)
+    }
+
+    async fn fetch(&self, reddit: &Reddit) -> Result<(Vec<String>, Vec<Subreddit>)> {
+        let text = reddit.fetch_wiki_page(&self.subreddit, &self.page).await?;
+        let list = parse_wiki_markdown(&text, &self.origin());
+        if !list.unparsed.is_empty() {
+            warn!("{} lines of {} could not be parsed.", list.unparsed.len(), self.origin());
+            for (line_no, line) in list.unparsed.iter() {
+                info!("Unparsed line {line_no}: {line}");
+            }
+        }
+        Ok((list.sections, list.subreddits))
+    }
+}
+
+/// The subreddits found on a wiki page.
+#[derive(Debug, Default)]
+pub struct WikiList {
+    /// Headings that have at least one subreddit under them, in page order.
+    pub sections: Vec<String>,
+    /// Every subreddit, once, under the first heading it appears in.
+    pub subreddits: Vec<Subreddit>,
+    /// Line numbers (1-based) and text of list items and table rows without a subreddit in them.
+    pub unparsed: Vec<(usize, String)>,
+}
+
+/// Returns the heading text if the line is a markdown heading.
+fn parse_heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let text = line[level..].trim().trim_end_matches('#').trim();
+    let text = text.trim_matches(['*', '_']).trim().trim_end_matches(':').trim();
+    Some(text.to_string())
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Finds every subreddit mentioned in a line, in any of the forms
+/// `r/foo`, `/r/foo`, `[r/foo](...)` or `https://www.reddit.com/r/foo/`.
+pub fn extract_subreddit_names(line: &str) -> Vec<String> {
+    // Reddit's editor escapes underscores.
+    let line = line.replace("\\_", "_");
+    let mut names: Vec<String> = Vec::new();
+    let mut start = 0;
+    while let Some(offset) = line[start..].find("r/") {
+        let idx = start + offset;
+        let boundary = line[..idx].chars().last().map(|c| !is_name_char(c)).unwrap_or(true);
+        let name: String = line[idx + 2..].chars().take_while(|c| is_name_char(*c)).collect();
+        if boundary && (2..=21).contains(&name.len()) && !names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+            names.push(name.clone());
+        }
+        start = idx + 2 + name.len();
+    }
+    names.into_iter().map(|n| format!("r/{n}")).collect()
+}
+
+/// True for lines that should hold an entry: list items and table rows.
+fn is_entry_line(line: &str) -> bool {
+    let bullet = line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ");
+    let numbered = line.split_once(". ").map(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())).unwrap_or(false);
+    bullet || numbered || line.starts_with('|')
+}
+
+/// True for lines that start with a subreddit reference, like `r/foo` or `[/r/foo](...)`.
+fn is_reference_line(line: &str) -> bool {
+    let line = line.trim_start_matches(['[', '*', '_']);
+    ["r/", "/r/", "http://", "https://", "reddit.com/", "www.reddit.com/", "old.reddit.com/"]
+        .iter()
+        .any(|p| line.starts_with(p))
+}
+
+/// True for table separator rows like `|---|:---:|`.
+fn is_table_separator(line: &str) -> bool {
+    line.starts_with('|') && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+/// Extracts the subreddit list from wiki markdown.
+/// Only list items, table rows and lines starting with a subreddit reference are read, so prose mentioning a
+/// subreddit is not picked up. Headings become sections. Names are deduplicated case-insensitively.
+pub fn parse_wiki_markdown(text: &str, origin: &str) -> WikiList {
+    let mut list = WikiList::default();
+    let mut current_section = "".to_string();
+    let mut in_code_block = false;
+    let mut seen: HashSet<SubredditId> = HashSet::new();
+    let lines = text.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+    for (idx, line) in lines.iter().enumerate() {
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block || line.is_empty() || is_table_separator(line) {
+            continue;
+        }
+        if let Some(heading) = parse_heading(line) {
+            current_section = heading;
+            continue;
+        }
+        // Table headers sit right above the separator row.
+        let is_table_header = line.starts_with('|') && lines.get(idx + 1).map(|l| is_table_separator(l)).unwrap_or(false);
+        if is_table_header || !(is_entry_line(line) || is_reference_line(line)) {
+            continue;
+        }
+
+        let names = extract_subreddit_names(line);
+        if names.is_empty() {
+            list.unparsed.push((idx + 1, line.to_string()));
+            continue;
+        }
+        for name in names {
+            if !seen.insert(SubredditId::new(&name)) {
+                continue;
+            }
+            let sub = new_subreddit(&name, Some(&current_section), origin);
+            if !list.sections.contains(&sub.section) {
+                list.sections.push(sub.section.clone());
+            }
+            list.subreddits.push(sub);
+        }
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use serde::Deserialize;
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Expected {
+        sections: Vec<String>,
+        subreddits: Vec<(String, String)>,
+        unparsed: Vec<usize>,
+    }
+
+    #[test]
+    fn wiki_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/wiki");
+        let mut count = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path).unwrap();
+            let expected: Expected = serde_json::from_str(&std::fs::read_to_string(path.with_extension("json")).unwrap()).unwrap();
+            let list = parse_wiki_markdown(&text, "test");
+
+            let subreddits = list.subreddits.iter()
+                .map(|s| (s.name.clone(), s.section.clone()))
+                .collect::<Vec<(String, String)>>();
+            let unparsed = list.unparsed.iter().map(|(n, _)| *n).collect::<Vec<usize>>();
+            assert_eq!(list.sections, expected.sections, "sections of {}", path.display());
+            assert_eq!(subreddits, expected.subreddits, "subreddits of {}", path.display());
+            assert_eq!(unparsed, expected.unparsed, "unparsed lines of {}", path.display());
+            count += 1;
+        }
+        assert!(count > 0, "no fixtures in {}", dir.display());
+    }
+
+    #[test]
+    fn extracts_name_forms() {
+        assert_eq!(extract_subreddit_names("r/foo"), vec!["r/foo"]);
+        assert_eq!(extract_subreddit_names("/r/foo"), vec!["r/foo"]);
+        assert_eq!(extract_subreddit_names("[r/foo](https://reddit.com/r/foo)"), vec!["r/foo"]);
+        assert_eq!(extract_subreddit_names("https://www.reddit.com/r/Foo_Bar/"), vec!["r/Foo_Bar"]);
+        assert_eq!(extract_subreddit_names("r/foo\\_bar and r/baz"), vec!["r/foo_bar", "r/baz"]);
+        assert!(extract_subreddit_names("u/foo and for/example").is_empty());
+    }
+}
@@ -1,25 +1,64 @@
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand, ValueEnum};
-use redis::aio::{Connection, PubSub};
-use tokio::sync::Mutex;
 use tracing::info;
-use crate::reddit::backend::direct::DirectBackend;
+use crate::admin::{AdminAction, AdminToken};
+use crate::list_source::ListSourceSpec;
+use crate::reddit::backend::direct::{DirectBackend, DirectBackendConfig};
+use crate::reddit::backend::oauth::{OAuthBackend, OAuthCredential};
+use crate::reddit::backend::pool::{PoolBackend, PoolMember};
+use crate::reddit::backend::proxy::ProxyBackend;
 use crate::reddit::backend::tor::TorBackend;
+use crate::reddit::backend::RedditRequestBackend;
+use crate::reddit::id::SubredditId;
 use crate::reddit::Reddit;
+use crate::sections::{SizeBucket, SizeSectioning};
+use crate::state_override::OverrideListSpec;
+use crate::tracker::Tracker;
 
+mod admin;
+mod lease;
+mod list_source;
 mod reddit;
 mod redis_helper;
+mod sections;
 mod update_list;
 mod server;
+mod state_override;
+mod tracker;
 mod updater;
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialOrd, PartialEq, ValueEnum)]
 pub enum RedditBackendSelector {
     DIRECT,
     TOR,
+    OAUTH,
+    POOL,
+    PROXY,
+}
+
+/// A member of the backend pool, as `backend` or `backend=weight`.
+#[derive(Copy, Clone, Debug)]
+pub struct PoolMemberSpec {
+    backend: RedditBackendSelector,
+    weight: u32,
+}
+
+impl FromStr for PoolMemberSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (backend, weight) = s.split_once('=').unwrap_or((s, "1"));
+        Ok(PoolMemberSpec {
+            backend: RedditBackendSelector::from_str(backend, true).map_err(|e| anyhow::anyhow!(e))?,
+            weight: weight.parse()?,
+        })
+    }
 }
 
 #[derive(Parser)]
@@ -29,31 +68,130 @@ pub struct Cli {
     #[clap(long = "redis-url", short = 'r', default_value = "redis://127.0.0.1/")]
     redis_url: String,
 
+    /// Work on the named tracker's list instead of the default one. Each tracker keeps its own keys in Redis.
+    #[clap(long = "tracker", short = 't')]
+    tracker: Option<Tracker>,
+
     #[clap(long = "reddit-backend", default_value = "tor")]
     reddit_backend: RedditBackendSelector,
 
     #[clap(long = "rate-limit", default_value = "1")]
     rate_limit: f32,
 
+    /// Number of isolated Tor circuits to spread requests over. Each gets its own rate limit.
+    #[clap(long = "tor-circuits", default_value = "1")]
+    tor_circuits: NonZeroUsize,
+
+    /// Idle connections kept open to reddit by the direct backend.
+    #[clap(long = "direct-pool-size", default_value = "32")]
+    direct_pool_size: usize,
+
+    /// Seconds to wait for a connection to reddit in the direct backend.
+    #[clap(long = "direct-connect-timeout", default_value = "10")]
+    direct_connect_timeout: u64,
+
+    /// Seconds a whole request may take in the direct backend.
+    #[clap(long = "direct-timeout", default_value = "30")]
+    direct_timeout: u64,
+
+    /// Allow HTTP/2 in the direct backend.
+    #[clap(long = "direct-http2")]
+    direct_http2: bool,
+
+    /// Request gzip compressed responses in the direct backend.
+    #[clap(long = "direct-gzip")]
+    direct_gzip: bool,
+
+    /// OAuth app credentials, as client_id:client_secret[:username:password]. Can be repeated.
+    #[clap(long = "oauth-credential")]
+    oauth_credentials: Vec<OAuthCredential>,
+
+    #[clap(long = "oauth-user-agent", default_value = concat!("web:reddark:", env!("CARGO_PKG_VERSION")))]
+    oauth_user_agent: String,
+
+    #[clap(long = "oauth-token-url", default_value = crate::reddit::backend::oauth::REDDIT_TOKEN_URL)]
+    oauth_token_url: String,
+
+    #[clap(long = "oauth-api-url", default_value = crate::reddit::backend::oauth::REDDIT_OAUTH_URL)]
+    oauth_api_url: String,
+
+    /// Backends used by the pool backend, as backend[=weight]. Can be repeated.
+    #[clap(long = "pool-member")]
+    pool_members: Vec<PoolMemberSpec>,
+
+    /// Consecutive failures before a pool member is taken out of rotation.
+    #[clap(long = "pool-max-failures", default_value = "3")]
+    pool_max_failures: u32,
+
+    /// Seconds a failing pool member stays out of rotation.
+    #[clap(long = "pool-cooldown", default_value = "60")]
+    pool_cooldown: u64,
+
+    /// File with one HTTP(S) or SOCKS5 proxy url per line, for the proxy backend.
+    #[clap(long = "proxy-list", default_value = "proxies.txt")]
+    proxy_list: PathBuf,
+
+    /// Seconds between checks of the proxy list for changes.
+    #[clap(long = "proxy-reload-interval", default_value = "30")]
+    proxy_reload_interval: u64,
+
+    /// Consecutive failures before a proxy is taken out of rotation.
+    #[clap(long = "proxy-max-failures", default_value = "3")]
+    proxy_max_failures: u32,
+
+    /// Seconds a failing or rate limited proxy stays out of rotation.
+    #[clap(long = "proxy-cooldown", default_value = "60")]
+    proxy_cooldown: u64,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 impl Cli {
-    pub async fn new_redis_connection(&self) -> Result<Arc<Mutex<Connection>>> {
-        let client = redis::Client::open(&*self.redis_url).unwrap();
-        Ok(Arc::new(Mutex::new(client.get_async_connection().await?)))
+    pub fn new_redis_client(&self) -> Result<redis::Client> {
+        redis::Client::open(&*self.redis_url).context("Invalid Redis URL")
     }
 
-    pub async fn new_redis_pubsub(&self) -> Result<PubSub> {
-        let client = redis::Client::open(&*self.redis_url).unwrap();
-        Ok(client.get_async_connection().await?.into_pubsub())
+    pub async fn new_reddit_backend(&self) -> Result<Arc<Reddit>> {
+        Ok(Reddit::new(self.new_request_backend(self.reddit_backend)?))
     }
 
-    pub async fn new_reddit_backend(&self) -> Result<Arc<Reddit>> {
-        match self.reddit_backend {
-            RedditBackendSelector::DIRECT => Ok(Reddit::new(DirectBackend::new(self.rate_limit)?)),
-            RedditBackendSelector::TOR => Ok(Reddit::new(TorBackend::new(self.rate_limit)?)),
+    fn new_request_backend(&self, selector: RedditBackendSelector) -> Result<Box<dyn RedditRequestBackend>> {
+        match selector {
+            RedditBackendSelector::DIRECT => Ok(DirectBackend::new(self.rate_limit, &DirectBackendConfig {
+                pool_size: self.direct_pool_size,
+                connect_timeout: Duration::from_secs(self.direct_connect_timeout),
+                timeout: Duration::from_secs(self.direct_timeout),
+                http2: self.direct_http2,
+                gzip: self.direct_gzip,
+            })?),
+            RedditBackendSelector::TOR => Ok(TorBackend::new(self.rate_limit, self.tor_circuits.get())?),
+            RedditBackendSelector::OAUTH => Ok(OAuthBackend::new(
+                self.rate_limit,
+                &self.oauth_credentials,
+                &self.oauth_user_agent,
+                &self.oauth_token_url,
+                &self.oauth_api_url,
+            )?),
+            RedditBackendSelector::PROXY => Ok(ProxyBackend::new(
+                self.rate_limit,
+                &self.proxy_list,
+                Duration::from_secs(self.proxy_reload_interval),
+                self.proxy_max_failures,
+                Duration::from_secs(self.proxy_cooldown),
+            )?),
+            RedditBackendSelector::POOL => {
+                let members = self.pool_members.iter()
+                    .map(|m| {
+                        if m.backend == RedditBackendSelector::POOL {
+                            return Err(anyhow::anyhow!("A backend pool cannot contain another pool"));
+                        }
+                        let name = format!("{:?}", m.backend).to_lowercase();
+                        Ok(PoolMember::new(&name, m.weight, self.new_request_backend(m.backend)?))
+                    })
+                    .collect::<Result<Vec<PoolMember>>>()?;
+                Ok(PoolBackend::new(members, self.pool_max_failures, Duration::from_secs(self.pool_cooldown))?)
+            }
         }
     }
 }
@@ -64,19 +202,73 @@ pub enum Commands {
     UpdateSubredditList {
         #[clap(long = "period", short = 'p')]
         period: Option<NonZeroU32>,
+        /// Where to read the list from, as wiki:<subreddit>/<page>, file:<path>, url:<url> or multi:<user>/<name>.
+        /// Can be repeated to merge several lists.
+        #[clap(long = "source", short = 's', default_value = "wiki:ModCoord/index")]
+        sources: Vec<ListSourceSpec>,
+        /// Section subreddits by their subscriber count: never, only those their source doesn't section (missing), or always.
+        #[clap(long = "size-sections", value_enum, default_value = "never")]
+        size_sections: SizeSectioning,
+        /// A subscriber size bucket for --size-sections, as MIN=NAME, e.g. 1m=1+ million.
+        /// Can be repeated. Defaults to the r/ModCoord size headings.
+        #[clap(long = "size-bucket")]
+        size_buckets: Vec<SizeBucket>,
     },
     /// Serve the pages
     Server {
         #[clap(long = "listen", short = 'l', default_value = "0.0.0.0:4000")]
         listen: String,
+        /// Leave subreddits that dropped off their source list out of the page.
+        #[clap(long = "hide-withdrawn")]
+        hide_withdrawn: bool,
+        /// Also serve the named tracker, under /t/<tracker>/. Can be repeated.
+        #[clap(long = "serve-tracker")]
+        serve_trackers: Vec<Tracker>,
+        /// A token for the admin API, as name:token. The name is recorded on every change made with it.
+        /// Can be repeated. Without any, the admin API rejects every request.
+        #[clap(long = "admin-token")]
+        admin_tokens: Vec<AdminToken>,
+        /// Directory to load page templates from. Each template is served at /view/<name>, and index.html at /.
+        #[clap(long = "templates", default_value = "templates")]
+        template_dir: PathBuf,
+        /// YAML file with the page text and branding. Defaults to Reddark's.
+        #[clap(long = "branding")]
+        branding: Option<PathBuf>,
+        /// Reload the templates and branding when they change. Every server listening on the same Redis reloads with it.
+        #[clap(long = "watch-templates")]
+        watch_templates: bool,
     },
     Updater {
         #[clap(long = "period", short = 'p')]
         period: Option<NonZeroU32>,
+        /// Hours to keep polling subreddits after they dropped off their source list.
+        #[clap(long = "withdrawn-grace", default_value = "24")]
+        withdrawn_grace: i64,
+        /// Lists forcing a state on subreddits, as <STATE>=url:<url>, <STATE>=file:<path> or <STATE>=redis:<set key>.
+        /// Can be repeated, earlier lists win.
+        #[clap(long = "override-list", default_value = state_override::DEFAULT_OVERRIDE_LIST)]
+        override_lists: Vec<OverrideListSpec>,
+        /// Seconds to cache override lists for.
+        #[clap(long = "override-refresh", default_value = "300")]
+        override_refresh: u64,
+        /// Don't detect protest modes like John Oliver theming, NSFW or text-only from subreddit content.
+        #[clap(long = "no-protest-detection")]
+        no_protest_detection: bool,
+        /// Also read the recent post titles of public subreddits for protest detection. Costs a request per subreddit.
+        #[clap(long = "scan-posts")]
+        scan_posts: bool,
+    },
+    /// Pin states, and add notes and blackout schedules to subreddits
+    Admin {
+        /// Who to record as making the change.
+        #[clap(long = "author", default_value = "cli")]
+        author: String,
+        #[command(subcommand)]
+        action: AdminAction,
     },
     Check {
         #[clap(long = "subreddit", short = 's')]
-        subreddit: String,
+        subreddit: SubredditId,
     }
 }
 
@@ -88,14 +280,31 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match &cli.command {
-        Commands::UpdateSubredditList { period } => {
-            update_list::update_list(&cli, *period).await?;
+        Commands::UpdateSubredditList { period, sources, size_sections, size_buckets } => {
+            let mut size_buckets = if size_buckets.is_empty() { sections::default_size_buckets() } else { size_buckets.clone() };
+            sections::sort_size_buckets(&mut size_buckets);
+            update_list::update_list(&cli, *period, sources, *size_sections, &size_buckets).await?;
+        }
+        Commands::Server { listen, hide_withdrawn, serve_trackers, admin_tokens, template_dir, branding, watch_templates } => {
+            let site_config = server::SiteConfig {
+                template_dir: template_dir.clone(),
+                branding: branding.clone(),
+            };
+            server::server(&cli, listen, *hide_withdrawn, serve_trackers, admin_tokens, site_config, *watch_templates).await?;
         }
-        Commands::Server { listen } => {
-            server::server(&cli, &listen).await?;
+        Commands::Updater { period, withdrawn_grace, override_lists, override_refresh, no_protest_detection, scan_posts } => {
+            updater::updater(
+                &cli,
+                *period,
+                chrono::Duration::hours(*withdrawn_grace),
+                override_lists,
+                Duration::from_secs(*override_refresh),
+                !*no_protest_detection,
+                *scan_posts,
+            ).await?;
         }
-        Commands::Updater { period } => {
-            updater::updater(&cli, *period).await?;
+        Commands::Admin { author, action } => {
+            admin::admin(&cli, author, action).await?;
         }
         Commands::Check { subreddit } => {
             let reddit = cli.new_reddit_backend().await?;
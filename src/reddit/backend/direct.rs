@@ -1,21 +1,45 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use governor::{clock, RateLimiter, state::{InMemoryState, NotKeyed}, middleware::NoOpMiddleware, Quota, Jitter};
 use nonzero_ext::nonzero;
 use serde_json::Value;
 use async_trait::async_trait;
+use tracing::debug;
 use crate::reddit::backend::RedditRequestBackend;
 
+#[derive(Clone, Debug)]
+pub struct DirectBackendConfig {
+    pub pool_size: usize,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub http2: bool,
+    pub gzip: bool,
+}
+
 pub struct DirectBackend {
     limiter: RateLimiter<NotKeyed, InMemoryState, clock::DefaultClock, NoOpMiddleware>,
+    client: reqwest::Client,
 }
 
 impl DirectBackend {
-    pub fn new(rate_limit: f32) -> anyhow::Result<Box<Self>> {
+    pub fn new(rate_limit: f32, config: &DirectBackendConfig) -> anyhow::Result<Box<Self>> {
         assert!(rate_limit > 0.0);
         let replenish_interval_ns = Duration::from_secs_f64(Duration::from_secs(1).as_secs_f64() / (rate_limit as f64));
         let limiter = RateLimiter::direct(Quota::with_period(replenish_interval_ns).unwrap().allow_burst(nonzero!(1u32)));
+        let client = reqwest::Client::builder()
+            .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/114.0")
+            .pool_max_idle_per_host(config.pool_size)
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.timeout)
+            .tcp_keepalive(Duration::from_secs(60))
+            .gzip(config.gzip);
+        let client = if config.http2 {
+            client
+        } else {
+            client.http1_only()
+        };
         Ok(Box::new(DirectBackend {
             limiter,
+            client: client.build()?,
         }))
     }
 }
@@ -24,10 +48,7 @@ impl DirectBackend {
 impl RedditRequestBackend for DirectBackend {
     async fn make_reddit_request(&self, rel_url: &str, query: Option<&[(String, String)]>) -> anyhow::Result<Value> {
         self.limiter.until_ready_with_jitter(Jitter::up_to(Duration::from_millis(1))).await;
-        let client = reqwest::Client::builder();
-        let client = client.user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/114.0");
-        let client = client.build()?;
-        let req = client.get(format!("https://old.reddit.com/{}", rel_url));
+        let req = self.client.get(format!("https://old.reddit.com/{}", rel_url));
         let req = if let Some(q) = query {
             req.query(q)
         } else {
@@ -35,12 +56,16 @@ impl RedditRequestBackend for DirectBackend {
         };
         let req = req.header("Range", "bytes=0-50");
         //info!("Sending request! {req:?}");
+        let start = Instant::now();
         let resp = req.send().await?;
+        debug!("Request for {rel_url} returned {} after {}ms ({:?})", resp.status(), start.elapsed().as_millis(), resp.version());
         if resp.status().is_success() || resp.status() == 403 || resp.status() == 404 {
-            Ok(resp.json().await?)
+            let value = resp.json().await?;
+            debug!("Request for {rel_url} completed after {}ms", start.elapsed().as_millis());
+            Ok(value)
         } else {
             let s = format!("{resp:?}");
             Err(anyhow::anyhow!("Error querying reddit: {s} {}", resp.text().await?))
         }
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,17 @@
 use async_trait::async_trait;
 
 pub mod direct;
+pub mod oauth;
+pub mod pool;
+pub mod proxy;
 pub mod tor;
 
 #[async_trait]
 pub trait RedditRequestBackend: Sync + Send {
     async fn make_reddit_request(&self, rel_url: &str, query: Option<&[(String, String)]>) -> anyhow::Result<serde_json::Value>;
+
+    /// Human readable health of the backend, if it has any to report.
+    async fn status(&self) -> Option<String> {
+        None
+    }
 }
@@ -0,0 +1,342 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use governor::{clock, RateLimiter, state::{InMemoryState, NotKeyed}, middleware::NoOpMiddleware, Quota, Jitter};
+use nonzero_ext::nonzero;
+use serde::Deserialize;
+use serde_json::Value;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use crate::reddit::backend::RedditRequestBackend;
+
+pub const REDDIT_TOKEN_URL: &str = "https://www.reddit.com/api/v1/access_token";
+pub const REDDIT_OAUTH_URL: &str = "https://oauth.reddit.com";
+
+// Refresh tokens this long before reddit says they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+// How long a rate limited credential is left alone when reddit doesn't send a Retry-After.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// A set of reddit app credentials.
+/// Either `client_id:client_secret` for an app-only token,
+/// or `client_id:client_secret:username:password` for a script app.
+#[derive(Clone, Debug)]
+pub struct OAuthCredential {
+    pub client_id: String,
+    pub client_secret: String,
+    pub login: Option<(String, String)>,
+}
+
+impl FromStr for OAuthCredential {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts = s.splitn(4, ':').collect::<Vec<&str>>();
+        match parts.as_slice() {
+            [id, secret] => Ok(OAuthCredential {
+                client_id: id.to_string(),
+                client_secret: secret.to_string(),
+                login: None,
+            }),
+            [id, secret, user, pass] => Ok(OAuthCredential {
+                client_id: id.to_string(),
+                client_secret: secret.to_string(),
+                login: Some((user.to_string(), pass.to_string())),
+            }),
+            _ => Err(anyhow::anyhow!("Credential must be client_id:client_secret[:username:password]")),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+struct Token {
+    access_token: String,
+    expires_at: Instant,
+}
+
+struct OAuthClient {
+    credential: OAuthCredential,
+    limiter: RateLimiter<NotKeyed, InMemoryState, clock::DefaultClock, NoOpMiddleware>,
+    token: Mutex<Option<Token>>,
+    /// Set when reddit answers 429. The client is skipped until then.
+    limited_until: std::sync::Mutex<Option<Instant>>,
+}
+
+impl OAuthClient {
+    fn limited_until(&self) -> Option<Instant> {
+        self.limited_until.lock().unwrap().filter(|until| *until > Instant::now())
+    }
+}
+
+pub struct OAuthBackend {
+    client: reqwest::Client,
+    token_url: String,
+    api_url: String,
+    clients: Vec<OAuthClient>,
+    next: AtomicUsize,
+}
+
+impl OAuthBackend {
+    pub fn new(rate_limit: f32, credentials: &[OAuthCredential], user_agent: &str, token_url: &str, api_url: &str) -> anyhow::Result<Box<Self>> {
+        assert!(rate_limit > 0.0);
+        if credentials.is_empty() {
+            return Err(anyhow::anyhow!("OAuth backend needs at least one credential"));
+        }
+        let replenish_interval_ns = Duration::from_secs_f64(Duration::from_secs(1).as_secs_f64() / (rate_limit as f64));
+        let clients = credentials.iter()
+            .map(|credential| OAuthClient {
+                credential: credential.clone(),
+                limiter: RateLimiter::direct(Quota::with_period(replenish_interval_ns).unwrap().allow_burst(nonzero!(1u32))),
+                token: Mutex::new(None),
+                limited_until: std::sync::Mutex::new(None),
+            })
+            .collect();
+        let client = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .build()?;
+        Ok(Box::new(OAuthBackend {
+            client,
+            token_url: token_url.to_string(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            clients,
+            next: AtomicUsize::new(0),
+        }))
+    }
+
+    async fn fetch_token(&self, credential: &OAuthCredential) -> anyhow::Result<Token> {
+        info!("Fetching new OAuth token for client {}...", credential.client_id);
+        let form = if let Some((username, password)) = &credential.login {
+            vec![("grant_type", "password"), ("username", username.as_str()), ("password", password.as_str())]
+        } else {
+            vec![("grant_type", "client_credentials")]
+        };
+        let resp = self.client.post(&self.token_url)
+            .basic_auth(&credential.client_id, Some(&credential.client_secret))
+            .form(&form)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            let s = format!("{resp:?}");
+            return Err(anyhow::anyhow!("Error fetching OAuth token: {s} {}", resp.text().await?));
+        }
+        let token: TokenResponse = resp.json().await.context("Unable to parse OAuth token response")?;
+        Ok(Token {
+            access_token: token.access_token,
+            expires_at: Instant::now() + Duration::from_secs(token.expires_in).saturating_sub(TOKEN_EXPIRY_MARGIN),
+        })
+    }
+
+    async fn get_token(&self, client: &OAuthClient) -> anyhow::Result<String> {
+        let mut token = client.token.lock().await;
+        match token.as_ref() {
+            Some(t) if t.expires_at > Instant::now() => Ok(t.access_token.clone()),
+            _ => {
+                let new_token = self.fetch_token(&client.credential).await?;
+                let access_token = new_token.access_token.clone();
+                *token = Some(new_token);
+                Ok(access_token)
+            }
+        }
+    }
+
+    /// The next client in turn that isn't rate limited.
+    /// If they all are, waits for the one that is free first.
+    async fn next_client(&self) -> &OAuthClient {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut first_free: Option<(&OAuthClient, Instant)> = None;
+        for i in 0..self.clients.len() {
+            let client = &self.clients[(start + i) % self.clients.len()];
+            match client.limited_until() {
+                None => {
+                    // Keep the rotation going from the client after this one.
+                    self.next.fetch_add(i, Ordering::Relaxed);
+                    return client;
+                }
+                Some(until) if first_free.map(|(_, f)| until < f).unwrap_or(true) => first_free = Some((client, until)),
+                Some(_) => {}
+            }
+        }
+        let (client, until) = first_free.unwrap();
+        info!("Every OAuth client is rate limited, waiting {:?}...", until - Instant::now());
+        tokio::time::sleep_until(until.into()).await;
+        client
+    }
+}
+
+#[async_trait]
+impl RedditRequestBackend for OAuthBackend {
+    async fn make_reddit_request(&self, rel_url: &str, query: Option<&[(String, String)]>) -> anyhow::Result<Value> {
+        // A rate limited client is set aside and the request is tried with the next one, at most once per client.
+        for _ in 0..self.clients.len() {
+            let client = self.next_client().await;
+            client.limiter.until_ready_with_jitter(Jitter::up_to(Duration::from_millis(1))).await;
+
+            // Retry once with a fresh token if reddit rejects the current one.
+            let mut retried = false;
+            loop {
+                let token = self.get_token(client).await?;
+                let req = self.client.get(format!("{}/{}", self.api_url, rel_url.trim_start_matches('/')));
+                let req = if let Some(q) = query {
+                    req.query(q)
+                } else {
+                    req
+                };
+                let resp = req.bearer_auth(token).send().await?;
+                if resp.status().is_success() || resp.status() == 403 || resp.status() == 404 {
+                    return Ok(resp.json().await?);
+                } else if resp.status() == 401 && !retried {
+                    info!("OAuth token for client {} was rejected, refreshing...", client.credential.client_id);
+                    *client.token.lock().await = None;
+                    retried = true;
+                } else if resp.status() == 429 {
+                    let retry_after = resp.headers().get(reqwest::header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().parse().ok())
+                        .map(Duration::from_secs)
+                        .unwrap_or(DEFAULT_RETRY_AFTER);
+                    warn!("OAuth client {} is rate limited, setting it aside for {retry_after:?}", client.credential.client_id);
+                    *client.limited_until.lock().unwrap() = Some(Instant::now() + retry_after);
+                    break;
+                } else {
+                    let s = format!("{resp:?}");
+                    return Err(anyhow::anyhow!("Error querying reddit: {s} {}", resp.text().await?));
+                }
+            }
+        }
+        Err(anyhow::anyhow!("Error querying reddit: every OAuth client is rate limited"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use axum::extract::State;
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use serde_json::json;
+    use super::*;
+
+    /// Stands in for reddit. Credentials are script apps, so the token request names the user, and tokens are `<user>-<n>`.
+    #[derive(Default)]
+    struct Stub {
+        expires_in: u64,
+        /// Users in the order tokens were issued to them.
+        issued: Vec<String>,
+        /// Tokens answered with 401.
+        rejected: HashSet<String>,
+        /// Users answered with 429.
+        rate_limited: HashSet<String>,
+        /// Users in the order their API requests were answered.
+        served: Vec<String>,
+    }
+
+    type StubState = Arc<std::sync::Mutex<Stub>>;
+
+    async fn token(State(stub): State<StubState>, Form(form): Form<HashMap<String, String>>) -> Json<Value> {
+        let mut stub = stub.lock().unwrap();
+        let user = form["username"].clone();
+        stub.issued.push(user.clone());
+        Json(json!({ "access_token": format!("{user}-{}", stub.issued.len()), "expires_in": stub.expires_in }))
+    }
+
+    async fn about(State(stub): State<StubState>, headers: HeaderMap) -> Response {
+        let mut stub = stub.lock().unwrap();
+        let token = headers[header::AUTHORIZATION].to_str().unwrap().trim_start_matches("Bearer ").to_string();
+        let user = token.split('-').next().unwrap().to_string();
+        if stub.rejected.contains(&token) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        if stub.rate_limited.contains(&user) {
+            return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "30")]).into_response();
+        }
+        stub.served.push(user);
+        Json(json!({ "kind": "t5" })).into_response()
+    }
+
+    fn start_stub(stub: Stub, users: &[&str]) -> (StubState, Box<OAuthBackend>) {
+        let stub = Arc::new(std::sync::Mutex::new(stub));
+        let app = Router::new()
+            .route("/token", post(token))
+            .route("/r/test/about", get(about))
+            .with_state(stub.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+        let credentials = users.iter()
+            .map(|user| format!("id-{user}:secret:{user}:password").parse().unwrap())
+            .collect::<Vec<OAuthCredential>>();
+        let backend = OAuthBackend::new(1000.0, &credentials, "test", &format!("http://{addr}/token"), &format!("http://{addr}/")).unwrap();
+        (stub, backend)
+    }
+
+    #[tokio::test]
+    async fn fetches_and_reuses_token() {
+        let (stub, backend) = start_stub(Stub { expires_in: 3600, ..Default::default() }, &["a"]);
+        for _ in 0..3 {
+            assert_eq!(backend.make_reddit_request("/r/test/about", None).await.unwrap(), json!({ "kind": "t5" }));
+        }
+        let stub = stub.lock().unwrap();
+        assert_eq!(stub.issued, ["a"]);
+        assert_eq!(stub.served, ["a", "a", "a"]);
+    }
+
+    #[tokio::test]
+    async fn refreshes_expired_token() {
+        // Inside the expiry margin, so every token is already expired when it arrives.
+        let (stub, backend) = start_stub(Stub { expires_in: 30, ..Default::default() }, &["a"]);
+        backend.make_reddit_request("/r/test/about", None).await.unwrap();
+        backend.make_reddit_request("/r/test/about", None).await.unwrap();
+        assert_eq!(stub.lock().unwrap().issued, ["a", "a"]);
+    }
+
+    #[tokio::test]
+    async fn retries_once_on_rejected_token() {
+        let stub = Stub { expires_in: 3600, rejected: HashSet::from(["a-1".to_string()]), ..Default::default() };
+        let (stub, backend) = start_stub(stub, &["a"]);
+        backend.make_reddit_request("/r/test/about", None).await.unwrap();
+        assert_eq!(stub.lock().unwrap().issued, ["a", "a"]);
+
+        stub.lock().unwrap().rejected.extend(["a-2".to_string(), "a-3".to_string(), "a-4".to_string()]);
+        *backend.clients[0].token.lock().await = None;
+        assert!(backend.make_reddit_request("/r/test/about", None).await.is_err());
+        // One fresh token for the request, and one for its single retry.
+        assert_eq!(stub.lock().unwrap().issued.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn rotates_credentials() {
+        let (stub, backend) = start_stub(Stub { expires_in: 3600, ..Default::default() }, &["a", "b", "c"]);
+        for _ in 0..6 {
+            backend.make_reddit_request("/r/test/about", None).await.unwrap();
+        }
+        let stub = stub.lock().unwrap();
+        assert_eq!(stub.issued, ["a", "b", "c"]);
+        assert_eq!(stub.served, ["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn sets_aside_rate_limited_credential() {
+        let stub = Stub { expires_in: 3600, rate_limited: HashSet::from(["a".to_string()]), ..Default::default() };
+        let (stub, backend) = start_stub(stub, &["a", "b"]);
+        for _ in 0..3 {
+            backend.make_reddit_request("/r/test/about", None).await.unwrap();
+        }
+        assert_eq!(stub.lock().unwrap().served, ["b", "b", "b"]);
+        let until = backend.clients[0].limited_until().unwrap();
+        assert!(until > Instant::now() + Duration::from_secs(25));
+        // Only the first request went to the rate limited credential.
+        assert_eq!(stub.lock().unwrap().issued, ["a", "b"]);
+    }
+}
@@ -0,0 +1,226 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use async_trait::async_trait;
+use tracing::{info, warn};
+use crate::reddit::backend::RedditRequestBackend;
+
+struct MemberState {
+    current_weight: i64,
+    failures: u32,
+    disabled_until: Option<Instant>,
+}
+
+pub struct PoolMember {
+    name: String,
+    weight: i64,
+    backend: Box<dyn RedditRequestBackend>,
+}
+
+impl PoolMember {
+    pub fn new(name: &str, weight: u32, backend: Box<dyn RedditRequestBackend>) -> Self {
+        PoolMember {
+            name: name.to_string(),
+            weight: weight.max(1) as i64,
+            backend,
+        }
+    }
+}
+
+/// Spreads requests over several backends by weight.
+/// A backend that fails `max_failures` times in a row is taken out of rotation for `cooldown`.
+pub struct PoolBackend {
+    members: Vec<PoolMember>,
+    states: Mutex<Vec<MemberState>>,
+    max_failures: u32,
+    cooldown: Duration,
+}
+
+impl PoolBackend {
+    pub fn new(members: Vec<PoolMember>, max_failures: u32, cooldown: Duration) -> anyhow::Result<Box<Self>> {
+        if members.is_empty() {
+            return Err(anyhow::anyhow!("Backend pool needs at least one member"));
+        }
+        let states = members.iter()
+            .map(|_| MemberState {
+                current_weight: 0,
+                failures: 0,
+                disabled_until: None,
+            })
+            .collect();
+        Ok(Box::new(PoolBackend {
+            members,
+            states: Mutex::new(states),
+            max_failures: max_failures.max(1),
+            cooldown,
+        }))
+    }
+
+    /// Smooth weighted round-robin over the healthy members not in `tried`.
+    /// If every remaining member is out of rotation, they are all considered anyway.
+    fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let mut states = self.states.lock().unwrap();
+        let now = Instant::now();
+        for (idx, state) in states.iter_mut().enumerate() {
+            if state.disabled_until.is_some_and(|t| t <= now) {
+                info!("Backend {} is back in rotation.", self.members[idx].name);
+                state.disabled_until = None;
+                state.failures = 0;
+            }
+        }
+
+        let candidates = (0..self.members.len())
+            .filter(|idx| !tried.contains(idx))
+            .collect::<Vec<usize>>();
+        let healthy = candidates.iter()
+            .copied()
+            .filter(|idx| states[*idx].disabled_until.is_none())
+            .collect::<Vec<usize>>();
+        let candidates = if healthy.is_empty() { candidates } else { healthy };
+
+        let total: i64 = candidates.iter().map(|idx| self.members[*idx].weight).sum();
+        let mut best: Option<usize> = None;
+        for idx in candidates {
+            states[idx].current_weight += self.members[idx].weight;
+            best = match best {
+                Some(b) if states[b].current_weight >= states[idx].current_weight => Some(b),
+                _ => Some(idx),
+            };
+        }
+        if let Some(idx) = best {
+            states[idx].current_weight -= total;
+        }
+        best
+    }
+
+    fn record_success(&self, idx: usize) {
+        let mut states = self.states.lock().unwrap();
+        states[idx].failures = 0;
+    }
+
+    fn record_failure(&self, idx: usize) {
+        let mut states = self.states.lock().unwrap();
+        let state = &mut states[idx];
+        state.failures += 1;
+        if state.failures >= self.max_failures && state.disabled_until.is_none() {
+            warn!("Backend {} failed {} times in a row, taking it out of rotation for {}s.", self.members[idx].name, state.failures, self.cooldown.as_secs());
+            state.disabled_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[async_trait]
+impl RedditRequestBackend for PoolBackend {
+    async fn make_reddit_request(&self, rel_url: &str, query: Option<&[(String, String)]>) -> anyhow::Result<Value> {
+        // Try each member at most once before giving up.
+        let mut tried = Vec::new();
+        let mut last_error = None;
+        while let Some(idx) = self.pick(&tried) {
+            let member = &self.members[idx];
+            match member.backend.make_reddit_request(rel_url, query).await {
+                Ok(v) => {
+                    self.record_success(idx);
+                    return Ok(v);
+                }
+                Err(e) => {
+                    warn!("Backend {} failed request for {rel_url}: {e}", member.name);
+                    self.record_failure(idx);
+                    tried.push(idx);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No backends available")))
+    }
+
+    async fn status(&self) -> Option<String> {
+        let mut lines = Vec::new();
+        for (idx, member) in self.members.iter().enumerate() {
+            let (failures, disabled) = {
+                let states = self.states.lock().unwrap();
+                (states[idx].failures, states[idx].disabled_until.is_some())
+            };
+            let mut line = format!("{}: {} failures{}", member.name, failures, if disabled { ", out of rotation" } else { "" });
+            if let Some(s) = member.backend.status().await {
+                line.push_str(&format!(" ({s})"));
+            }
+            lines.push(line);
+        }
+        Some(lines.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use serde_json::json;
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct FakeBackend {
+        failing: Arc<AtomicBool>,
+        hits: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl RedditRequestBackend for FakeBackend {
+        async fn make_reddit_request(&self, _rel_url: &str, _query: Option<&[(String, String)]>) -> anyhow::Result<Value> {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                Err(anyhow::anyhow!("failing"))
+            } else {
+                Ok(json!({}))
+            }
+        }
+    }
+
+    fn pool(members: &[(&str, u32, &FakeBackend)], cooldown: Duration) -> Box<PoolBackend> {
+        let members = members.iter()
+            .map(|(name, weight, backend)| PoolMember::new(name, *weight, Box::new((*backend).clone())))
+            .collect();
+        PoolBackend::new(members, 1, cooldown).unwrap()
+    }
+
+    async fn request(pool: &PoolBackend, times: usize) {
+        for _ in 0..times {
+            pool.make_reddit_request("/r/test/about.json", None).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn spreads_requests_by_weight() {
+        let (a, b) = (FakeBackend::default(), FakeBackend::default());
+        let pool = pool(&[("a", 3, &a), ("b", 1, &b)], Duration::from_secs(60));
+        request(&pool, 8).await;
+        assert_eq!(a.hits.load(Ordering::SeqCst), 6);
+        assert_eq!(b.hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn skips_disabled_member() {
+        let (a, b) = (FakeBackend::default(), FakeBackend::default());
+        a.failing.store(true, Ordering::SeqCst);
+        let pool = pool(&[("a", 3, &a), ("b", 1, &b)], Duration::from_secs(60));
+        request(&pool, 5).await;
+        // Tried once, then out of rotation.
+        assert_eq!(a.hits.load(Ordering::SeqCst), 1);
+        assert_eq!(b.hits.load(Ordering::SeqCst), 5);
+        assert_eq!(pool.status().await.unwrap(), "a: 1 failures, out of rotation; b: 0 failures");
+    }
+
+    #[tokio::test]
+    async fn recovers_after_cooldown() {
+        let (a, b) = (FakeBackend::default(), FakeBackend::default());
+        a.failing.store(true, Ordering::SeqCst);
+        let pool = pool(&[("a", 1, &a), ("b", 1, &b)], Duration::from_millis(50));
+        request(&pool, 2).await;
+        assert_eq!(a.hits.load(Ordering::SeqCst), 1);
+
+        a.failing.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        request(&pool, 4).await;
+        assert_eq!(a.hits.load(Ordering::SeqCst), 3);
+        assert!(pool.states.lock().unwrap().iter().all(|s| s.disabled_until.is_none()));
+    }
+}
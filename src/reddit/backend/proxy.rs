@@ -0,0 +1,338 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use anyhow::Context;
+use governor::{clock, RateLimiter, state::{InMemoryState, NotKeyed}, middleware::NoOpMiddleware, Quota, Jitter};
+use nonzero_ext::nonzero;
+use serde_json::Value;
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use crate::reddit::backend::RedditRequestBackend;
+
+const OLD_REDDIT_URL: &str = "https://old.reddit.com";
+
+struct ProxyHealth {
+    failures: u32,
+    disabled_until: Option<Instant>,
+}
+
+struct Proxy {
+    url: String,
+    client: reqwest::Client,
+    limiter: RateLimiter<NotKeyed, InMemoryState, clock::DefaultClock, NoOpMiddleware>,
+    health: Mutex<ProxyHealth>,
+}
+
+impl Proxy {
+    fn new(url: &str, rate_limit: f32) -> anyhow::Result<Self> {
+        let replenish_interval_ns = Duration::from_secs_f64(Duration::from_secs(1).as_secs_f64() / (rate_limit as f64));
+        let limiter = RateLimiter::direct(Quota::with_period(replenish_interval_ns).unwrap().allow_burst(nonzero!(1u32)));
+        let client = reqwest::Client::builder()
+            .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/114.0")
+            .proxy(reqwest::Proxy::all(url).with_context(|| format!("Invalid proxy {url}"))?)
+            .build()?;
+        Ok(Proxy {
+            url: url.to_string(),
+            client,
+            limiter,
+            health: Mutex::new(ProxyHealth {
+                failures: 0,
+                disabled_until: None,
+            }),
+        })
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap();
+        health.disabled_until.map(|t| t <= now).unwrap_or(true)
+    }
+
+    fn record_success(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.failures = 0;
+        health.disabled_until = None;
+    }
+
+    fn record_failure(&self, max_failures: u32, cooldown: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        if health.failures >= max_failures {
+            warn!("Proxy {} failed {} times in a row, taking it out of rotation for {}s.", self.url, health.failures, cooldown.as_secs());
+            health.disabled_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    fn rate_limited(&self, cooldown: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.disabled_until = Some(Instant::now() + cooldown);
+    }
+}
+
+struct ProxyList {
+    proxies: Vec<Arc<Proxy>>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// Sends requests to old.reddit.com through a list of HTTP(S) or SOCKS5 proxies.
+/// The list is read from a file with one proxy url per line, and re-read when the file changes.
+pub struct ProxyBackend {
+    base_url: String,
+    path: PathBuf,
+    rate_limit: f32,
+    reload_interval: Duration,
+    max_failures: u32,
+    cooldown: Duration,
+    list: RwLock<ProxyList>,
+    next: AtomicUsize,
+}
+
+fn parse_proxy_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect()
+}
+
+async fn read_proxy_file(path: &Path) -> anyhow::Result<(Vec<String>, Option<SystemTime>)> {
+    let modified = tokio::fs::metadata(path).await?.modified().ok();
+    let text = tokio::fs::read_to_string(path).await?;
+    Ok((parse_proxy_list(&text), modified))
+}
+
+impl ProxyBackend {
+    pub fn new(rate_limit: f32, path: &Path, reload_interval: Duration, max_failures: u32, cooldown: Duration) -> anyhow::Result<Box<Self>> {
+        Self::with_base_url(OLD_REDDIT_URL, rate_limit, path, reload_interval, max_failures, cooldown)
+    }
+
+    /// Sends requests to `base_url` instead of old.reddit.com.
+    fn with_base_url(base_url: &str, rate_limit: f32, path: &Path, reload_interval: Duration, max_failures: u32, cooldown: Duration) -> anyhow::Result<Box<Self>> {
+        assert!(rate_limit > 0.0);
+        let read = || anyhow::Ok((parse_proxy_list(&std::fs::read_to_string(path)?), std::fs::metadata(path)?.modified().ok()));
+        let (urls, modified) = read().with_context(|| format!("Unable to read proxy list {}", path.display()))?;
+        let proxies = urls.iter()
+            .map(|u| Ok(Arc::new(Proxy::new(u, rate_limit)?)))
+            .collect::<anyhow::Result<Vec<Arc<Proxy>>>>()?;
+        info!("Loaded {} proxies from {}.", proxies.len(), path.display());
+        Ok(Box::new(ProxyBackend {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            path: path.to_path_buf(),
+            rate_limit,
+            reload_interval,
+            max_failures: max_failures.max(1),
+            cooldown,
+            list: RwLock::new(ProxyList {
+                proxies,
+                modified,
+                checked: Instant::now(),
+            }),
+            next: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Re-read the proxy file if it changed. Proxies that are still listed keep their limiter and health.
+    async fn maybe_reload(&self) -> anyhow::Result<()> {
+        if self.list.read().await.checked.elapsed() < self.reload_interval {
+            return Ok(());
+        }
+        // Read before taking the lock, so requests don't wait on the file system.
+        let read = read_proxy_file(&self.path).await;
+        let mut list = self.list.write().await;
+        if list.checked.elapsed() < self.reload_interval {
+            // Another request reloaded it meanwhile.
+            return Ok(());
+        }
+        list.checked = Instant::now();
+        let (urls, modified) = match read {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Unable to re-read proxy list {}, keeping current list: {e}", self.path.display());
+                return Ok(());
+            }
+        };
+        if modified.is_some() && modified == list.modified {
+            return Ok(());
+        }
+        let proxies = urls.iter()
+            .map(|u| {
+                if let Some(p) = list.proxies.iter().find(|p| &p.url == u) {
+                    Ok(p.clone())
+                } else {
+                    Ok(Arc::new(Proxy::new(u, self.rate_limit)?))
+                }
+            })
+            .collect::<anyhow::Result<Vec<Arc<Proxy>>>>()?;
+        info!("Reloaded proxy list {}, now using {} proxies.", self.path.display(), proxies.len());
+        list.proxies = proxies;
+        list.modified = modified;
+        Ok(())
+    }
+
+    /// Round-robin over the proxies not in `tried`, preferring ones that are in rotation.
+    async fn pick(&self, tried: &[String]) -> Option<Arc<Proxy>> {
+        let list = self.list.read().await;
+        let now = Instant::now();
+        let candidates = list.proxies.iter()
+            .filter(|p| !tried.contains(&p.url))
+            .collect::<Vec<&Arc<Proxy>>>();
+        let healthy = candidates.iter()
+            .copied()
+            .filter(|p| p.is_available(now))
+            .collect::<Vec<&Arc<Proxy>>>();
+        let candidates = if healthy.is_empty() { candidates } else { healthy };
+        if candidates.is_empty() {
+            return None;
+        }
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        Some(candidates[idx].clone())
+    }
+}
+
+#[async_trait]
+impl RedditRequestBackend for ProxyBackend {
+    async fn make_reddit_request(&self, rel_url: &str, query: Option<&[(String, String)]>) -> anyhow::Result<Value> {
+        self.maybe_reload().await?;
+
+        let mut tried = Vec::new();
+        let mut last_error = None;
+        while let Some(proxy) = self.pick(&tried).await {
+            tried.push(proxy.url.clone());
+            proxy.limiter.until_ready_with_jitter(Jitter::up_to(Duration::from_millis(1))).await;
+
+            let req = proxy.client.get(format!("{}/{}", self.base_url, rel_url.trim_start_matches('/')));
+            let req = if let Some(q) = query {
+                req.query(q)
+            } else {
+                req
+            };
+            let resp = match req.send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("Request through proxy {} failed: {e}", proxy.url);
+                    proxy.record_failure(self.max_failures, self.cooldown);
+                    last_error = Some(anyhow::Error::from(e));
+                    continue;
+                }
+            };
+            if resp.status().is_success() || resp.status() == 403 || resp.status() == 404 {
+                proxy.record_success();
+                return Ok(resp.json().await?);
+            } else if resp.status() == 429 {
+                // Rate limit!
+                // Rotate to the next proxy.
+                info!("Proxy {} got rate limited, rotating...", proxy.url);
+                proxy.rate_limited(self.cooldown);
+                last_error = Some(anyhow::anyhow!("Rate limited through proxy {}", proxy.url));
+            } else {
+                proxy.record_failure(self.max_failures, self.cooldown);
+                let s = format!("{resp:?}");
+                last_error = Some(anyhow::anyhow!("Error querying reddit: {s} {}", resp.text().await?));
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No proxies available")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+    use axum::{Json, Router};
+    use serde_json::json;
+    use super::*;
+
+    /// Stands in for a proxy. Requests to the plain http base url arrive in absolute form, and are answered with `status`.
+    fn start_proxy(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().fallback(move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response: Response = if status.is_success() {
+                    Json(json!({ "kind": "t5" })).into_response()
+                } else {
+                    status.into_response()
+                };
+                response
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+        (url, hits)
+    }
+
+    /// A proxy that refuses connections.
+    fn dead_proxy() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    fn proxy_file(name: &str, urls: &[&str]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("proxies-{}-{name}.txt", std::process::id()));
+        std::fs::write(&path, format!("# test proxies\n{}\n", urls.join("\n"))).unwrap();
+        path
+    }
+
+    fn backend(path: &Path, reload_interval: Duration) -> Box<ProxyBackend> {
+        ProxyBackend::with_base_url("http://reddit.invalid", 1000.0, path, reload_interval, 1, Duration::from_secs(60)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rotates_proxies() {
+        let (a, a_hits) = start_proxy(StatusCode::OK);
+        let (b, b_hits) = start_proxy(StatusCode::OK);
+        let backend = backend(&proxy_file("rotate", &[&a, &b]), Duration::from_secs(60));
+        for _ in 0..4 {
+            assert_eq!(backend.make_reddit_request("/r/test/about.json", None).await.unwrap(), json!({ "kind": "t5" }));
+        }
+        assert_eq!(a_hits.load(Ordering::SeqCst), 2);
+        assert_eq!(b_hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_over_and_takes_out_failing_proxies() {
+        let dead = dead_proxy();
+        let (limited, limited_hits) = start_proxy(StatusCode::TOO_MANY_REQUESTS);
+        let (ok, ok_hits) = start_proxy(StatusCode::OK);
+        let backend = backend(&proxy_file("failover", &[&dead, &limited, &ok]), Duration::from_secs(60));
+        for _ in 0..5 {
+            backend.make_reddit_request("/r/test/about.json", None).await.unwrap();
+        }
+        assert_eq!(ok_hits.load(Ordering::SeqCst), 5);
+        // Out of rotation after the first failure, so each was tried at most once.
+        assert!(limited_hits.load(Ordering::SeqCst) <= 1);
+        let list = backend.list.read().await;
+        let now = Instant::now();
+        let available = list.proxies.iter().filter(|p| p.is_available(now)).map(|p| p.url.clone()).collect::<Vec<String>>();
+        assert_eq!(available, [ok]);
+    }
+
+    #[tokio::test]
+    async fn fails_when_every_proxy_fails() {
+        let backend = backend(&proxy_file("all-dead", &[&dead_proxy(), &dead_proxy()]), Duration::from_secs(60));
+        assert!(backend.make_reddit_request("/r/test/about.json", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn picks_up_changed_proxy_file() {
+        let (a, a_hits) = start_proxy(StatusCode::OK);
+        let (b, b_hits) = start_proxy(StatusCode::OK);
+        let path = proxy_file("reload", &[&a]);
+        let backend = backend(&path, Duration::ZERO);
+        backend.make_reddit_request("/r/test/about.json", None).await.unwrap();
+
+        // Make sure the modification time moves.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        proxy_file("reload", &[&b]);
+        backend.make_reddit_request("/r/test/about.json", None).await.unwrap();
+        backend.make_reddit_request("/r/test/about.json", None).await.unwrap();
+        assert_eq!(a_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(b_hits.load(Ordering::SeqCst), 2);
+    }
+}
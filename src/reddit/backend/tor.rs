@@ -1,7 +1,8 @@
 use std::io::Read;
 use hyper::body::Buf;
-use std::ops::DerefMut;
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use anyhow::Context;
 use arti_client::{BootstrapBehavior, TorClient};
 use arti_client::config::{ClientAddrConfig, TorClientConfigBuilder};
@@ -11,6 +12,7 @@ use nonzero_ext::nonzero;
 use serde_json::Value;
 use async_trait::async_trait;
 use hyper::{Body, Client, Method, Request};
+use itertools::Itertools;
 use tor_rtcompat::PreferredRuntime;
 use crate::reddit::backend::RedditRequestBackend;
 use tls_api::{TlsConnector as TlsConnectorTrait, TlsConnectorBuilder};
@@ -32,17 +34,54 @@ fn create_hyper_client_from_tor_client(tor_client: &TorClient<PreferredRuntime>)
     Ok(client)
 }
 
-pub struct TorBackend {
+/// One isolated circuit, with its own rate limiter and counters.
+struct Circuit {
+    id: u64,
+    created: Instant,
+    client: Client<ArtiHttpConnector<PreferredRuntime, TlsConnector>>,
     limiter: RateLimiter<NotKeyed, InMemoryState, clock::DefaultClock, NoOpMiddleware>,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    total_ms: AtomicU64,
+}
+
+impl Circuit {
+    fn new(id: u64, tor_client: &TorClient<PreferredRuntime>, rate_limit: f32) -> anyhow::Result<Self> {
+        let replenish_interval_ns = Duration::from_secs_f64(Duration::from_secs(1).as_secs_f64() / (rate_limit as f64));
+        let limiter = RateLimiter::direct(Quota::with_period(replenish_interval_ns).unwrap().allow_burst(nonzero!(1u32)));
+        Ok(Circuit {
+            id,
+            created: Instant::now(),
+            client: create_hyper_client_from_tor_client(tor_client)?,
+            limiter,
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            total_ms: AtomicU64::new(0),
+        })
+    }
+
+    fn status(&self) -> String {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let avg_ms = self.total_ms.load(Ordering::Relaxed).checked_div(requests).unwrap_or(0);
+        format!("circuit {} (age {}s): {} requests, {} failed, {}ms avg",
+                self.id, self.created.elapsed().as_secs(), requests, self.failures.load(Ordering::Relaxed), avg_ms)
+    }
+}
+
+pub struct TorBackend {
+    rate_limit: f32,
     tor_client: TorClient<PreferredRuntime>,
-    client: RwLock<Client<ArtiHttpConnector<PreferredRuntime, TlsConnector>>>,
+    circuits: Vec<RwLock<Arc<Circuit>>>,
+    next: AtomicUsize,
+    next_id: AtomicU64,
+    retired: AtomicU64,
 }
 
 impl TorBackend {
-    pub fn new(rate_limit: f32) -> anyhow::Result<Box<Self>> {
+    /// Creates a backend with `circuits` isolated circuits, each allowed `rate_limit` requests per second.
+    pub fn new(rate_limit: f32, circuits: usize) -> anyhow::Result<Box<Self>> {
         assert!(rate_limit > 0.0);
-        let replenish_interval_ns = Duration::from_secs_f64(Duration::from_secs(1).as_secs_f64() / (rate_limit as f64));
-        let limiter = RateLimiter::direct(Quota::with_period(replenish_interval_ns).unwrap().allow_burst(nonzero!(1u32)));
+        assert!(circuits > 0);
 
         let mut tor_config = TorClientConfigBuilder::default();
         *tor_config.address_filter() = ClientAddrConfig::builder().allow_onion_addrs(true).clone();
@@ -52,20 +91,39 @@ impl TorBackend {
             .create_unbootstrapped()
             .context("Unable to create Tor client!")?;
 
-        let client = RwLock::new(create_hyper_client_from_tor_client(&tor_client)?);
+        let circuits = (0..circuits)
+            .map(|id| anyhow::Ok(RwLock::new(Arc::new(Circuit::new(id as u64, &tor_client, rate_limit)?))))
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
         Ok(Box::new(TorBackend {
-            limiter,
+            rate_limit,
+            next_id: AtomicU64::new(circuits.len() as u64),
             tor_client,
-            client,
+            circuits,
+            next: AtomicUsize::new(0),
+            retired: AtomicU64::new(0),
         }))
     }
+
+    /// Replaces the circuit in `slot`, unless another request already did.
+    async fn retire_circuit(&self, slot: usize, circuit: &Arc<Circuit>) -> anyhow::Result<()> {
+        let mut current = self.circuits[slot].write().await;
+        if Arc::ptr_eq(&current, circuit) {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            info!("Retiring rate limited {}, replacing with circuit {id}.", circuit.status());
+            *current = Arc::new(Circuit::new(id, &self.tor_client, self.rate_limit)?);
+            self.retired.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl RedditRequestBackend for TorBackend {
     async fn make_reddit_request(&self, rel_url: &str, query: Option<&[(String, String)]>) -> anyhow::Result<Value> {
-        self.limiter.until_ready_with_jitter(Jitter::up_to(Duration::from_millis(1))).await;
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.circuits.len();
+        let circuit = self.circuits[slot].read().await.clone();
+        circuit.limiter.until_ready_with_jitter(Jitter::up_to(Duration::from_millis(1))).await;
 
         let uri = format!("https://{}/", REDDIT_TOR_HOST);
         let mut uri = url::Url::parse(&uri)?;
@@ -92,25 +150,27 @@ impl RedditRequestBackend for TorBackend {
             .header("Cookie", "_options=%7B%22pref_quarantine_optin%22%3A%20true%2C%20%22pref_gated_sr_optin%22%3A%20true%7D")
             .body(Body::empty())?;
 
-        let response = {
-            let client = self.client.read().await;
-            client.request(request).await?
+        let start = Instant::now();
+        circuit.requests.fetch_add(1, Ordering::Relaxed);
+        let response = match circuit.client.request(request).await {
+            Ok(r) => r,
+            Err(e) => {
+                circuit.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(e.into());
+            }
         };
+        circuit.total_ms.fetch_add(start.elapsed().as_millis() as u64, Ordering::Relaxed);
 
         if response.status().is_success() || response.status() == 403 || response.status() == 404 {
             let body = hyper::body::aggregate(response).await?;
             let value: Value = serde_json::from_reader(body.reader())?;
             Ok(value)
         } else {
+            circuit.failures.fetch_add(1, Ordering::Relaxed);
             if response.status() == 429 {
                 // Rate limit!
-                // Cycle out circuit.
-                {
-                    let mut client = self.client.write().await;
-                    let new_client = create_hyper_client_from_tor_client(&self.tor_client)?;
-                    let old = std::mem::replace(client.deref_mut(), new_client);
-                    drop(old);
-                }
+                // Cycle out this circuit only.
+                self.retire_circuit(slot, &circuit).await?;
                 // Retry.
                 self.make_reddit_request(rel_url, query).await
             } else {
@@ -122,4 +182,13 @@ impl RedditRequestBackend for TorBackend {
             }
         }
     }
-}
\ No newline at end of file
+
+    async fn status(&self) -> Option<String> {
+        let mut circuits = Vec::new();
+        for c in self.circuits.iter() {
+            circuits.push(c.read().await.status());
+        }
+        Some(format!("tor bootstrap: {}; {} circuits retired; {}",
+                     self.tor_client.bootstrap_status(), self.retired.load(Ordering::Relaxed), circuits.iter().join("; ")))
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use crate::reddit::SubredditState;
+
+/// The parts of a subreddit's about data that protests show up in.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SubredditAbout {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub public_description: Option<String>,
+    #[serde(default)]
+    pub over18: Option<bool>,
+    /// `any`, `link` or `self`.
+    #[serde(default)]
+    pub submission_type: Option<String>,
+    #[serde(default)]
+    pub icon_img: Option<String>,
+    #[serde(default)]
+    pub community_icon: Option<String>,
+    #[serde(default)]
+    pub banner_img: Option<String>,
+    #[serde(default)]
+    pub banner_background_image: Option<String>,
+    #[serde(default)]
+    pub subscribers: Option<u64>,
+    #[serde(default)]
+    pub accounts_active: Option<u64>,
+    #[serde(default)]
+    pub active_user_count: Option<u64>,
+}
+
+impl SubredditAbout {
+    pub fn icon(&self) -> String {
+        self.community_icon.clone().filter(|s| !s.is_empty()).or_else(|| self.icon_img.clone()).unwrap_or_default()
+    }
+
+    pub fn banner(&self) -> String {
+        self.banner_background_image.clone().filter(|s| !s.is_empty()).or_else(|| self.banner_img.clone()).unwrap_or_default()
+    }
+}
+
+/// How a subreddit looked while it was running normally, to compare later observations against.
+#[derive(Clone, Debug, Serialize, Deserialize, Ord, PartialOrd, PartialEq, Eq)]
+pub struct SubredditBaseline {
+    pub over18: bool,
+    pub submission_type: String,
+    pub icon: String,
+    pub banner: String,
+    /// Missing from baselines taken before they were kept, in which case title and description changes aren't judged.
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl From<&SubredditAbout> for SubredditBaseline {
+    fn from(about: &SubredditAbout) -> Self {
+        SubredditBaseline {
+            over18: about.over18.unwrap_or(false),
+            submission_type: about.submission_type.clone().unwrap_or_default(),
+            icon: about.icon(),
+            banner: about.banner(),
+            title: Some(about.title.clone().unwrap_or_default()),
+            description: Some(about.public_description.clone().unwrap_or_default()),
+        }
+    }
+}
+
+/// A protest mode recognised from a subreddit's content.
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub state: SubredditState,
+    pub reason: String,
+}
+
+const OLIVER_PHRASES: [&str; 3] = ["john oliver", "last week tonight", "#oliver"];
+
+// Share of recent posts that have to mention John Oliver.
+const OLIVER_POST_SHARE: f32 = 0.3;
+const OLIVER_MIN_POSTS: usize = 3;
+
+fn mentions_oliver(text: &str) -> bool {
+    let text = text.to_lowercase();
+    OLIVER_PHRASES.iter().any(|p| text.contains(p))
+}
+
+/// Classifies a public subreddit's protest mode, if it is running one.
+/// Checks, in order: John Oliver theming in the title, description or recent post titles,
+/// a switch to NSFW, and a switch to text posts only.
+/// Everything but the post titles needs a baseline to compare against, so subreddits that always mention John Oliver aren't flagged.
+pub fn detect_protest(about: &SubredditAbout, baseline: Option<&SubredditBaseline>, post_titles: &[String]) -> Option<Detection> {
+    let newly_mentioned = |now: Option<&str>, before: Option<&Option<String>>| {
+        matches!(before, Some(Some(before)) if !mentions_oliver(before)) && mentions_oliver(now.unwrap_or(""))
+    };
+    if newly_mentioned(about.title.as_deref(), baseline.map(|b| &b.title))
+        || newly_mentioned(about.public_description.as_deref(), baseline.map(|b| &b.description)) {
+        return Some(Detection { state: SubredditState::OLIVER, reason: "title or description newly mentions John Oliver".to_string() });
+    }
+
+    let oliver_posts = post_titles.iter().filter(|t| mentions_oliver(t)).count();
+    let restyled = baseline.map(|b| b.icon != about.icon() || b.banner != about.banner()).unwrap_or(false);
+    if post_titles.len() >= OLIVER_MIN_POSTS && oliver_posts as f32 / post_titles.len() as f32 >= OLIVER_POST_SHARE {
+        return Some(Detection { state: SubredditState::OLIVER, reason: format!("{oliver_posts} of {} recent posts mention John Oliver", post_titles.len()) });
+    }
+    if restyled && oliver_posts > 0 {
+        return Some(Detection { state: SubredditState::OLIVER, reason: "new icon or banner, and recent posts mention John Oliver".to_string() });
+    }
+
+    if let Some(baseline) = baseline {
+        if about.over18 == Some(true) && !baseline.over18 {
+            return Some(Detection { state: SubredditState::NSFW, reason: "switched to NSFW".to_string() });
+        }
+        if about.submission_type.as_deref() == Some("self") && baseline.submission_type != "self" {
+            return Some(Detection { state: SubredditState::TEXTONLY, reason: "switched to text posts only".to_string() });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn about(title: &str, description: &str) -> SubredditAbout {
+        SubredditAbout {
+            title: Some(title.to_string()),
+            public_description: Some(description.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn is_oliver(detection: Option<Detection>) -> bool {
+        detection.is_some_and(|d| d.state == SubredditState::OLIVER)
+    }
+
+    #[test]
+    fn flags_new_oliver_mentions_only() {
+        let plain = SubredditBaseline::from(&about("Pictures of cats", "Cats."));
+        let themed = SubredditBaseline::from(&about("Last Week Tonight", "The show with John Oliver."));
+        let oliver = about("Pictures of John Oliver", "Cats.");
+
+        assert!(is_oliver(detect_protest(&oliver, Some(&plain), &[])));
+        assert!(is_oliver(detect_protest(&about("Pictures of cats", "Now #oliver only."), Some(&plain), &[])));
+        assert!(!is_oliver(detect_protest(&about("Last Week Tonight", "The show with John Oliver."), Some(&themed), &[])));
+        assert!(!is_oliver(detect_protest(&oliver, None, &[])));
+    }
+
+    #[test]
+    fn old_baselines_dont_judge_mentions() {
+        let baseline: SubredditBaseline = serde_json::from_str(r#"{"over18":false,"submission_type":"any","icon":"","banner":""}"#).unwrap();
+        assert!(!is_oliver(detect_protest(&about("Last Week Tonight", ""), Some(&baseline), &[])));
+    }
+}
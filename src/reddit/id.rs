@@ -0,0 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// Canonical identity of a subreddit: its name, lowercased, without any `r/` prefix or reddit url around it.
+/// Reddit treats subreddit names case-insensitively, so `r/AskReddit`, `/r/askreddit` and
+/// `https://old.reddit.com/r/AskReddit/` are all the same `askreddit`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SubredditId(String);
+
+impl SubredditId {
+    pub fn new(name: &str) -> Self {
+        let name = name.trim();
+        let name = name.strip_prefix("https://").or_else(|| name.strip_prefix("http://")).unwrap_or(name);
+        // Drop a reddit host, like `reddit.com` or `old.reddit.com`, along with its slash.
+        let name = match name.split_once('/') {
+            Some((host, path)) if host == "reddit.com" || host.ends_with(".reddit.com") => path,
+            _ => name,
+        };
+        // Only one `r/` is taken off, as `r/r` is a subreddit too.
+        let name = name.trim_start_matches('/');
+        let name = name.strip_prefix("r/").unwrap_or(name);
+        let name = name.split(['/', '?', '#']).next().unwrap_or("");
+        SubredditId(name.to_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The `r/name` form reddit urls are built from.
+    pub fn prefixed(&self) -> String {
+        format!("r/{}", self.0)
+    }
+}
+
+impl fmt::Display for SubredditId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for SubredditId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let id = SubredditId::new(s);
+        if id.0.is_empty() || !id.0.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow::anyhow!("Not a subreddit name: {s}"));
+        }
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(name: &str) -> String {
+        SubredditId::new(name).to_string()
+    }
+
+    #[test]
+    fn normalises_name_forms() {
+        for name in ["AskReddit", "askreddit", " r/AskReddit ", "/r/askreddit", "/r/AskReddit/", "r/AskReddit/top?t=day",
+                     "reddit.com/r/AskReddit", "https://www.reddit.com/r/AskReddit/", "http://old.reddit.com/r/askreddit#x",
+                     "https://new.reddit.com/r/AskReddit/comments/abc/title/"] {
+            assert_eq!(id(name), "askreddit", "{name}");
+        }
+    }
+
+    #[test]
+    fn strips_one_prefix_only() {
+        assert_eq!(id("r/r"), "r");
+        assert_eq!(id("/r/r/"), "r");
+        assert_eq!(id("r/r/AskReddit"), "r");
+        assert_eq!(id("https://reddit.com/r/r"), "r");
+        assert_eq!(id("oldschoolcool"), "oldschoolcool");
+        assert_eq!(id("newzealand"), "newzealand");
+        assert_eq!(id("www_fans"), "www_fans");
+        assert_eq!(id("/r/"), "");
+    }
+
+    #[test]
+    fn rejects_non_names() {
+        assert!("r/AskReddit".parse::<SubredditId>().is_ok());
+        assert!("".parse::<SubredditId>().is_err());
+        assert!("not a name".parse::<SubredditId>().is_err());
+        assert!("example.com/r/x".parse::<SubredditId>().is_err());
+    }
+}
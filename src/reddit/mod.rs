@@ -5,11 +5,16 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use strum::{EnumIter, IntoEnumIterator};
+use crate::admin::SubredditAnnotations;
 use crate::reddit::backend::RedditRequestBackend;
+use crate::reddit::detect::{SubredditAbout, SubredditBaseline};
+use crate::reddit::id::SubredditId;
+use crate::sections::{bucket_for, SizeBucket};
 
 pub mod backend;
+pub mod detect;
+pub mod id;
 
 #[derive(Clone, Debug, Copy, Ord, PartialOrd, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
 pub enum SubredditState {
@@ -19,6 +24,8 @@ pub enum SubredditState {
     ARCHIVED,
     OLIVER,
     RESTRICTED,
+    NSFW,
+    TEXTONLY,
 }
 
 impl SubredditState {
@@ -30,6 +37,8 @@ impl SubredditState {
             SubredditState::RESTRICTED => "restricted".to_string(),
             SubredditState::ARCHIVED => "archived".to_string(),
             SubredditState::OLIVER => "oliver".to_string(),
+            SubredditState::NSFW => "nsfw".to_string(),
+            SubredditState::TEXTONLY => "textonly".to_string(),
         }
     }
 
@@ -41,6 +50,8 @@ impl SubredditState {
             SubredditState::ARCHIVED => true,
             SubredditState::OLIVER => true,
             SubredditState::RESTRICTED => true,
+            SubredditState::NSFW => true,
+            SubredditState::TEXTONLY => true,
         }
     }
 
@@ -70,6 +81,9 @@ impl FromStr for SubredditState {
             "restricted" => Ok(SubredditState::RESTRICTED),
             "private" => Ok(SubredditState::PRIVATE),
             "archived" => Ok(SubredditState::ARCHIVED),
+            "oliver" => Ok(SubredditState::OLIVER),
+            "nsfw" => Ok(SubredditState::NSFW),
+            "textonly" => Ok(SubredditState::TEXTONLY),
             _ => Err(anyhow::anyhow!("No known state: {s}")),
         }
     }
@@ -80,11 +94,74 @@ pub struct Subreddit {
     pub name: String,
     pub section: String,
     pub state: SubredditState,
+    /// Which list source the subreddit came from.
+    #[serde(default)]
+    pub source: String,
+    /// When the subreddit dropped off its source list, if it has.
+    #[serde(default)]
+    pub withdrawn_at: Option<DateTime<Utc>>,
+    /// Reddit's `t5_` fullname, once the updater has seen the subreddit.
+    #[serde(default)]
+    pub fullname: Option<String>,
+    /// How the subreddit looked the last time no protest was detected on it.
+    #[serde(default)]
+    pub baseline: Option<SubredditBaseline>,
+    /// Why the current state was detected from the subreddit's content, if it was.
+    #[serde(default)]
+    pub detected: Option<String>,
+    #[serde(default)]
+    pub metadata: SubredditMetadata,
+    /// Pins, notes and schedules set by admins.
+    #[serde(default)]
+    pub annotations: SubredditAnnotations,
+    /// Bumped by every write, so a write based on an outdated read can be refused.
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// Details about a subreddit from reddit, refreshed by the updater whenever reddit returns them.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Ord, PartialOrd, PartialEq, Eq)]
+pub struct SubredditMetadata {
+    pub title: Option<String>,
+    pub subscribers: Option<u64>,
+    pub active_users: Option<u64>,
+    pub over18: Option<bool>,
+    pub icon: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl SubredditMetadata {
+    /// Merges in what reddit returned, keeping earlier values for anything it left out.
+    pub fn update(&mut self, about: &SubredditAbout) {
+        let icon = about.icon();
+        // Reddit html-escapes urls in about data.
+        let icon = Some(icon.replace("&amp;", "&")).filter(|i| !i.is_empty());
+        self.title = about.title.clone().or(self.title.take());
+        self.subscribers = about.subscribers.or(self.subscribers);
+        self.active_users = about.active_user_count.or(about.accounts_active).or(self.active_users);
+        self.over18 = about.over18.or(self.over18);
+        self.icon = icon.or(self.icon.take());
+        self.updated_at = Some(Utc::now());
+    }
+
+    /// The subscriber size bucket the subreddit falls in, if its size is known. `buckets` must be sorted largest first.
+    pub fn size_bucket(&self, buckets: &[SizeBucket]) -> Option<String> {
+        self.subscribers.and_then(|n| bucket_for(buckets, n)).map(|b| b.name.clone())
+    }
 }
 
 impl Subreddit {
-    pub fn safe_name(&self) -> String {
-        self.name.replace(|c: char| !c.is_alphanumeric(), "_").to_string()
+    pub fn id(&self) -> SubredditId {
+        SubredditId::new(&self.name)
+    }
+
+    pub fn is_withdrawn(&self) -> bool {
+        self.withdrawn_at.is_some()
+    }
+
+    /// True if the subreddit was withdrawn more than `grace` ago, and should no longer be polled.
+    pub fn withdrawn_for(&self, grace: chrono::Duration) -> bool {
+        self.withdrawn_at.map(|t| Utc::now() - t > grace).unwrap_or(false)
     }
 }
 
@@ -93,6 +170,11 @@ pub struct SubredditDelta {
     pub prev_state: SubredditState,
     pub subreddit: Subreddit,
     pub timestamp: DateTime<Utc>,
+    /// The admin behind the change, if it came from a pin.
+    #[serde(default)]
+    pub actor: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
 }
 
 impl From<Subreddit> for SubredditDelta {
@@ -101,10 +183,20 @@ impl From<Subreddit> for SubredditDelta {
             prev_state: value.state,
             subreddit: value,
             timestamp: Utc::now(),
+            actor: None,
+            reason: None,
         }
     }
 }
 
+/// What the bulk lookup learns about a subreddit.
+#[derive(Clone, Debug)]
+pub struct SubredditInfo {
+    pub state: SubredditState,
+    pub fullname: Option<String>,
+    pub about: SubredditAbout,
+}
+
 pub struct Reddit {
     backend: Box<dyn RedditRequestBackend>,
 }
@@ -116,19 +208,12 @@ impl Reddit {
         });
     }
 
-    pub async fn get_oliver_list(&self) -> Result<Vec<String>> {
-        let data = reqwest::get("https://raw.githubusercontent.com/username-is-required/reddark-subinfo/main/john-oliver-subs.json").await?;
-        let data: Value = data.json().await?;
-        let data = data.get("johnOliverSubs").ok_or_else(|| anyhow::anyhow!("Unable to find element"))?;
-        let data = data.as_array().ok_or_else(|| anyhow::anyhow!("Element is not array"))?;
-        let data = data.iter()
-            .map(|v| v.as_str().unwrap().to_string())
-            .collect();
-        Ok(data)
+    pub async fn backend_status(&self) -> Option<String> {
+        self.backend.status().await
     }
 
-    pub async fn get_subreddit_state(&self, name: &str) -> Result<SubredditState> {
-        let u = format!("{}/about.json", name);
+    pub async fn get_subreddit_state(&self, id: &SubredditId) -> Result<SubredditState> {
+        let u = format!("{}/about.json", id.prefixed());
         let data = self.backend.make_reddit_request(&u, None).await?;
         if let Some(reason) = data.get("reason") {
             let is_private = reason.as_str().unwrap_or("") == "private" || reason.as_str().unwrap_or("") == "banned";
@@ -155,11 +240,11 @@ impl Reddit {
         }
     }
 
-    pub async fn get_subreddit_state_bulk<T: ToString>(&self, names: &[T]) -> Result<BTreeMap<String, SubredditState>> {
-        if names.len() > 100 {
+    pub async fn get_subreddit_state_bulk(&self, ids: &[SubredditId]) -> Result<BTreeMap<SubredditId, SubredditInfo>> {
+        if ids.len() > 100 {
             return Err(anyhow::anyhow!("Too many names passed!"));
         }
-        let query = [("sr_name".to_string(), names.iter().map(|n| n.to_string().trim_start_matches("r/").trim().to_string()).join(","))];
+        let query = [("sr_name".to_string(), ids.iter().map(|id| id.as_str()).join(","))];
         let data = self.backend.make_reddit_request("api/info.json", Some(&query)).await?;
         let data = data
             .get("data")
@@ -181,39 +266,58 @@ impl Reddit {
                     .ok_or_else(|| anyhow::anyhow!("No subreddit display_name"))?
                     .as_str()
                     .ok_or_else(|| anyhow::anyhow!("Subreddit display_name is not a string"))?;
+                let fullname = sub.get("name")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string());
 
-                anyhow::Ok((name.to_lowercase().to_string(), SubredditState::from_str(state)?))
+                let about = serde_json::from_value(sub.clone()).unwrap_or_default();
+
+                anyhow::Ok((SubredditId::new(name), SubredditInfo {
+                    state: SubredditState::from_str(state)?,
+                    fullname,
+                    about,
+                }))
             })
             .collect()
     }
 
-    pub async fn fetch_subreddits(&self) -> Result<(Vec<String>, Vec<Subreddit>)> {
-        let data = self.backend.make_reddit_request("/r/ModCoord/wiki/index.json", None).await?;
+    pub async fn get_recent_post_titles(&self, id: &SubredditId, limit: usize) -> Result<Vec<String>> {
+        let query = [("limit".to_string(), limit.to_string())];
+        let data = self.backend.make_reddit_request(&format!("{}/new.json", id.prefixed()), Some(&query)).await?;
+        let children = data
+            .get("data")
+            .and_then(|v| v.get("children"))
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow::anyhow!("No children element"))?;
+        Ok(children.iter()
+            .filter_map(|c| c.get("data").and_then(|d| d.get("title")).and_then(|t| t.as_str()))
+            .map(|t| t.to_string())
+            .collect())
+    }
+
+    pub async fn fetch_wiki_page(&self, subreddit: &str, page: &str) -> Result<String> {
+        let data = self.backend.make_reddit_request(&format!("/r/{subreddit}/wiki/{page}.json"), None).await?;
         let text = data.get("data").and_then(|v| v.get("content_md")).ok_or(anyhow::anyhow!("Couldn't get content_md!"))?;
         let text = text.as_str().ok_or(anyhow::anyhow!("Can't parse text"))?;
+        Ok(text.to_string())
+    }
 
-        let mut current_section = "".to_string();
-        let mut subreddits = Vec::new();
-        let mut sections = Vec::new();
-        let mut new_section = false;
-        for line in text.lines() {
-            let line = line.trim();
-            if line.starts_with("##") && !line.contains("Please") && line.contains(":") {
-                current_section = line.replace("##", "").replace(":", "").trim().to_string();
-                new_section = true;
-            } else if line.starts_with("r/") {
-                if new_section {
-                    sections.push(current_section.clone());
-                    new_section = false;
-                }
-                subreddits.push(Subreddit {
-                    name: line.to_string(),
-                    section: current_section.clone(),
-                    state: SubredditState::UNKNOWN,
-                });
-            }
-        }
-
-        Ok((sections, subreddits))
+    pub async fn fetch_multireddit(&self, user: &str, name: &str) -> Result<Vec<String>> {
+        let data = self.backend.make_reddit_request(&format!("api/multi/user/{user}/m/{name}"), None).await?;
+        let data = data
+            .get("data")
+            .and_then(|v| v.get("subreddits"))
+            .ok_or_else(|| anyhow::anyhow!("No subreddits element"))?
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Subreddits is not array"))?;
+        data.iter()
+            .map(|v| {
+                let name = v.get("name")
+                    .ok_or_else(|| anyhow::anyhow!("No subreddit name"))?
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Subreddit name is not a string"))?;
+                anyhow::Ok(name.to_string())
+            })
+            .collect()
     }
-}
\ No newline at end of file
+}
@@ -1,29 +1,179 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use redis::aio::Connection;
+use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use redis::aio::{ConnectionLike, MultiplexedConnection, PubSub};
 use tokio::sync::Mutex;
 use anyhow::Result;
 use futures_util::TryStream;
 use futures_util::StreamExt;
-use redis::{AsyncCommands, Msg};
-use tracing::info;
+use chrono::Utc;
+use redis::{AsyncCommands, Cmd, Msg, Pipeline, RedisFuture, RedisResult, Script, Value};
+use tracing::{info, warn};
 use crate::Cli;
+use crate::admin::BlockAuditEntry;
 use crate::reddit::{Subreddit, SubredditDelta, SubredditState};
+use crate::reddit::id::SubredditId;
+use crate::sections::SizeBucket;
+use crate::state_override::OverrideList;
+use crate::tracker::Tracker;
+
+/// KEYS: subreddit hash, history list, update channel.
+/// ARGV: id, version it was read at, subreddit, delta to send or an empty string, repeated for each subreddit.
+/// A subreddit that isn't stored counts as version 0.
+const WRITE_SUBREDDITS_SCRIPT: &str = r#"
+local skipped = {}
+for i = 1, #ARGV, 4 do
+    local current = redis.call('HGET', KEYS[1], ARGV[i])
+    local version = 0
+    if current then
+        version = cjson.decode(current)['version'] or 0
+    end
+    if version == tonumber(ARGV[i + 1]) then
+        redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 2])
+        if ARGV[i + 3] ~= '' then
+            redis.call('LPUSH', KEYS[2], ARGV[i + 3])
+            redis.call('PUBLISH', KEYS[3], ARGV[i + 3])
+        end
+    else
+        table.insert(skipped, ARGV[i])
+    end
+end
+return skipped
+"#;
+
+const RENEW_LOCK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+const RELEASE_LOCK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// A multiplexed connection, shared by every clone so commands from all tasks go out without waiting on each other.
+/// When it breaks, the command that noticed fails and the next one opens a new connection.
+#[derive(Clone)]
+pub struct ManagedConnection {
+    client: redis::Client,
+    /// The connection, numbered so a connection is only dropped once however many commands fail on it.
+    current: Arc<Mutex<Option<(u64, MultiplexedConnection)>>>,
+    opened: Arc<AtomicU64>,
+}
+
+impl ManagedConnection {
+    pub async fn new(client: redis::Client) -> Result<Self> {
+        let con = Self {
+            client,
+            current: Arc::new(Mutex::new(None)),
+            opened: Arc::new(AtomicU64::new(0)),
+        };
+        // Fail early if Redis can't be reached at all.
+        con.connection().await?;
+        Ok(con)
+    }
+
+    async fn connection(&self) -> RedisResult<(u64, MultiplexedConnection)> {
+        let mut current = self.current.lock().await;
+        if let Some(con) = current.as_ref() {
+            return Ok(con.clone());
+        }
+        let con = self.client.get_multiplexed_tokio_connection().await?;
+        let id = self.opened.fetch_add(1, Ordering::Relaxed);
+        if id > 0 {
+            info!("Reconnected to Redis.");
+        }
+        *current = Some((id, con.clone()));
+        Ok((id, con))
+    }
+
+    async fn check<T>(&self, id: u64, result: RedisResult<T>) -> RedisResult<T> {
+        if let Err(e) = &result {
+            if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() || e.is_timeout() {
+                let mut current = self.current.lock().await;
+                if current.as_ref().is_some_and(|(current_id, _)| *current_id == id) {
+                    warn!("Lost the Redis connection, reconnecting on the next command: {e}");
+                    *current = None;
+                }
+            }
+        }
+        result
+    }
+}
+
+impl ConnectionLike for ManagedConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let (id, mut con) = self.connection().await?;
+            let result = con.req_packed_command(cmd).await;
+            self.check(id, result).await
+        })
+    }
+
+    fn req_packed_commands<'a>(&'a mut self, cmd: &'a Pipeline, offset: usize, count: usize) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let (id, mut con) = self.connection().await?;
+            let result = con.req_packed_commands(cmd, offset, count).await;
+            self.check(id, result).await
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.client.get_connection_info().redis.db
+    }
+}
 
 #[derive(Clone)]
 pub struct RedisHelper {
-    con: Arc<Mutex<Connection>>,
+    client: redis::Client,
+    con: ManagedConnection,
+    tracker: Option<Tracker>,
 }
 
 impl RedisHelper {
     pub async fn new(cli: &Cli) -> Result<Self> {
-        let con = cli.new_redis_connection().await?;
+        let client = cli.new_redis_client()?;
+        let con = ManagedConnection::new(client.clone()).await?;
         Ok(Self {
+            client,
             con,
+            tracker: cli.tracker.clone(),
         })
     }
+
+    /// A helper for another tracker, sharing this one's connection.
+    pub fn for_tracker(&self, tracker: Option<Tracker>) -> Self {
+        Self {
+            client: self.client.clone(),
+            con: self.con.clone(),
+            tracker,
+        }
+    }
+
+    /// A connection of its own for subscribing, as subscribed connections can't run other commands.
+    pub async fn new_pubsub(&self) -> Result<PubSub> {
+        Ok(self.client.get_async_connection().await?.into_pubsub())
+    }
+
+    pub fn tracker(&self) -> Option<&Tracker> {
+        self.tracker.as_ref()
+    }
+
+    fn key(&self, key: &str) -> String {
+        crate::tracker::key(self.tracker.as_ref(), key)
+    }
+
+    fn con(&self) -> ManagedConnection {
+        self.con.clone()
+    }
+
     pub async fn get_current_state(&self) -> Result<Vec<Subreddit>> {
-        let srs: HashMap<String, String> = self.con.lock().await.hgetall("subreddit").await?;
+        let srs: HashMap<String, String> = self.con().hgetall(self.key("subreddit")).await?;
         let values = srs.values()
             .map(|v| {
                 serde_json::from_str::<Subreddit>(v)
@@ -32,56 +182,152 @@ impl RedisHelper {
         Ok(values)
     }
 
-    pub async fn update_subreddit(&self, subreddit: &Subreddit) -> Result<()> {
-        let val = serde_json::to_string(&subreddit)?;
-        self.con.lock().await.hset("subreddit", subreddit.safe_name(), val).await?;
+    pub async fn get_subreddit(&self, id: &SubredditId) -> Result<Option<Subreddit>> {
+        let sub: Option<String> = self.con().hget(self.key("subreddit"), id.as_str()).await?;
+        Ok(sub.map(|s| serde_json::from_str(&s)).transpose()?)
+    }
+
+    /// Stores a subreddit, if it wasn't changed since it was read. Returns false if it was, leaving it as it is.
+    pub async fn update_subreddit(&self, subreddit: &Subreddit) -> Result<bool> {
+        Ok(self.write_subreddits(&[(subreddit, String::new())]).await?.is_empty())
+    }
+
+    /// Moves subreddits stored under older hash keys to their canonical `SubredditId` key.
+    /// Entries that collapse onto the same id are merged, preferring the one with a known state.
+    pub async fn migrate_subreddit_keys(&self) -> Result<()> {
+        let srs: HashMap<String, String> = self.con().hgetall(self.key("subreddit")).await?;
+        let mut merged: HashMap<String, Subreddit> = HashMap::new();
+        // Versions of the entries already under their canonical key, which the merged entries replace.
+        let mut versions: HashMap<String, u64> = HashMap::new();
+        let mut stale_keys = Vec::new();
+        for (key, value) in srs.iter() {
+            let sub = serde_json::from_str::<Subreddit>(value)?;
+            let id = sub.id().to_string();
+            if *key != id {
+                stale_keys.push(key.clone());
+            } else {
+                versions.insert(id.clone(), sub.version);
+            }
+            let keep = match merged.get(&id) {
+                Some(other) => other.state == SubredditState::UNKNOWN && sub.state != SubredditState::UNKNOWN,
+                None => true,
+            };
+            if keep {
+                merged.insert(id, sub);
+            }
+        }
+        if stale_keys.is_empty() {
+            return Ok(());
+        }
+        info!("Migrating {} subreddits to canonical keys...", stale_keys.len());
+        // Written like any other change, so an entry someone changed in the meantime keeps their change.
+        let merged = merged.into_iter()
+            .map(|(id, sub)| Subreddit { version: versions.get(&id).copied().unwrap_or(0), ..sub })
+            .collect::<Vec<Subreddit>>();
+        let writes = merged.iter().map(|sub| (sub, String::new())).collect::<Vec<(&Subreddit, String)>>();
+        self.write_subreddits(&writes).await?;
+        for key in stale_keys {
+            self.con().hdel(self.key("subreddit"), key).await?;
+        }
         Ok(())
     }
 
     pub async fn set_sections(&self, sections: Vec<String>) -> Result<()> {
         let val = serde_json::to_string(&sections)?;
-        self.con.lock().await.set("sections", val).await?;
+        self.con().set(self.key("sections"), val).await?;
         Ok(())
     }
 
+    /// The size buckets the list updater sections by, so the page labels sizes the same way.
+    pub async fn set_size_buckets(&self, buckets: &[SizeBucket]) -> Result<()> {
+        let val = serde_json::to_string(buckets)?;
+        self.con().set(self.key("size_buckets"), val).await?;
+        Ok(())
+    }
+
+    pub async fn get_size_buckets(&self) -> Result<Vec<SizeBucket>> {
+        let buckets: Option<String> = self.con().get(self.key("size_buckets")).await?;
+        match buckets {
+            Some(buckets) => Ok(serde_json::from_str(&buckets)?),
+            None => Ok(crate::sections::default_size_buckets()),
+        }
+    }
+
     pub async fn get_sections(&self) -> Result<Vec<String>> {
-        let sections: Option<String> = self.con.lock().await.get("sections").await?;
+        let sections: Option<String> = self.con().get(self.key("sections")).await?;
         if let Some(sections) = sections {
             Ok(serde_json::from_str(&sections)?)
         } else {
-            Ok(vec![
-                "40+ million".to_string(),
-                "30+ million".to_string(),
-                "20+ million".to_string(),
-                "10+ million".to_string(),
-                "5+ million".to_string(),
-                "1+ million".to_string(),
-                "500k+".to_string(),
-                "250k+".to_string(),
-                "100k+".to_string(),
-                "50k+".to_string(),
-                "5k+".to_string(),
-                "5k and below".to_string(),
-                "1k+".to_string(),
-                "1k and below".to_string(),
-            ])
+            Ok(crate::sections::DEFAULT_SECTIONS.iter().map(|s| s.to_string()).collect())
         }
     }
 
-    pub async fn send_delta(&self, delta: &SubredditDelta) -> Result<()> {
-        if delta.prev_state != SubredditState::UNKNOWN || (delta.prev_state == SubredditState::UNKNOWN && delta.subreddit.state == SubredditState::PRIVATE) {
-            info!("Sending subreddit delta for {}...", delta.subreddit.name);
-            let data = serde_json::to_string(&delta)?;
-            self.con.lock().await.lpush("historical_deltas", data.clone()).await?;
-            self.con.lock().await.publish("subreddit_updates", data).await?;
+    pub async fn set_state_overrides(&self, lists: &[OverrideList]) -> Result<()> {
+        let val = serde_json::to_string(lists)?;
+        self.con().set(self.key("state_overrides"), val).await?;
+        Ok(())
+    }
+
+    pub async fn get_state_overrides(&self) -> Result<Vec<OverrideList>> {
+        let lists: Option<String> = self.con().get(self.key("state_overrides")).await?;
+        if let Some(lists) = lists {
+            Ok(serde_json::from_str(&lists)?)
         } else {
-            info!("Skipping subreddit delta for {}.", delta.subreddit.name);
+            Ok(Vec::new())
         }
+    }
+
+    pub async fn get_set_members(&self, key: &str) -> Result<Vec<String>> {
+        Ok(self.con().smembers(key).await?)
+    }
+
+    pub async fn get_blocklist(&self) -> Result<HashSet<SubredditId>> {
+        let names: Vec<String> = self.con().smembers(self.key("blocklist")).await?;
+        Ok(names.iter().map(|n| SubredditId::new(n)).collect())
+    }
+
+    /// Whether the one-off migration `name` has run.
+    pub async fn is_migrated(&self, name: &str) -> Result<bool> {
+        Ok(self.con().exists(self.key(&format!("migrated:{name}"))).await?)
+    }
+
+    pub async fn set_migrated(&self, name: &str) -> Result<()> {
+        self.con().set(self.key(&format!("migrated:{name}")), Utc::now().to_rfc3339()).await?;
+        Ok(())
+    }
+
+    /// Adds to or removes from the blocklist. Returns false if the subreddit already was or wasn't on it.
+    pub async fn set_blocked(&self, id: &SubredditId, blocked: bool) -> Result<bool> {
+        let changed: i64 = if blocked {
+            self.con().sadd(self.key("blocklist"), id.as_str()).await?
+        } else {
+            self.con().srem(self.key("blocklist"), id.as_str()).await?
+        };
+        Ok(changed > 0)
+    }
+
+    pub async fn push_block_audit(&self, entry: &BlockAuditEntry) -> Result<()> {
+        let val = serde_json::to_string(entry)?;
+        self.con().lpush(self.key("blocklist_audit"), val).await?;
+        Ok(())
+    }
+
+    /// The newest `count` blocklist changes, newest first.
+    pub async fn get_block_audit(&self, count: isize) -> Result<Vec<BlockAuditEntry>> {
+        let data: Vec<String> = self.con().lrange(self.key("blocklist_audit"), 0, count - 1).await?;
+        Ok(data.iter()
+            .map(|e| serde_json::from_str(e))
+            .collect::<Result<Vec<BlockAuditEntry>, serde_json::Error>>()?)
+    }
+
+    /// Asks every server to reload its templates and every client its page.
+    pub async fn publish_reload(&self) -> Result<()> {
+        self.con().publish(crate::tracker::key(None, "reload"), "").await?;
         Ok(())
     }
 
     pub async fn get_hist_delta(&self) -> Result<Vec<SubredditDelta>> {
-        let data: Vec<String> = self.con.lock().await.lrange("historical_deltas", 0, 20).await?;
+        let data: Vec<String> = self.con().lrange(self.key("historical_deltas"), 0, 20).await?;
         let data = data.into_iter()
             .map(|e| anyhow::Ok(serde_json::from_str::<SubredditDelta>(&e)?))
             .collect::<Result<Vec<SubredditDelta>>>();
@@ -89,22 +335,96 @@ impl RedisHelper {
     }
 
     pub async fn trim_history(&self) -> Result<()> {
-        self.con.lock().await.ltrim("historical_deltas", 0, 10000).await?;
+        self.con().ltrim(self.key("historical_deltas"), 0, 10000).await?;
         Ok(())
     }
 
-    pub async fn apply_delta(&self, delta: &SubredditDelta) -> Result<()> {
-        self.update_subreddit(&delta.subreddit).await?;
-        if delta.prev_state != delta.subreddit.state {
-            self.send_delta(&delta).await?;
+    /// Applies a delta if the subreddit wasn't changed since it was read. Returns false if it was.
+    pub async fn apply_delta(&self, delta: &SubredditDelta) -> Result<bool> {
+        Ok(self.apply_deltas(std::slice::from_ref(delta)).await?.is_empty())
+    }
+
+    /// Stores the subreddits and sends out their state changes, in one atomic round trip.
+    /// Subreddits changed by someone else since they were read are left alone. Returns those.
+    pub async fn apply_deltas(&self, deltas: &[SubredditDelta]) -> Result<Vec<SubredditId>> {
+        let mut writes = Vec::with_capacity(deltas.len());
+        for delta in deltas {
+            let event = if delta.prev_state == delta.subreddit.state {
+                String::new()
+            } else if delta.prev_state != SubredditState::UNKNOWN || delta.subreddit.state == SubredditState::PRIVATE {
+                info!("Sending subreddit delta for {}...", delta.subreddit.name);
+                serde_json::to_string(&delta)?
+            } else {
+                info!("Skipping subreddit delta for {}.", delta.subreddit.name);
+                String::new()
+            };
+            writes.push((&delta.subreddit, event));
+        }
+        self.write_subreddits(&writes).await
+    }
+
+    /// Stores each subreddit with its version bumped, and sends out its event if it has one, in one atomic round trip.
+    /// Subreddits whose stored version is no longer the one they were read at are skipped, and returned.
+    async fn write_subreddits(&self, writes: &[(&Subreddit, String)]) -> Result<Vec<SubredditId>> {
+        if writes.is_empty() {
+            return Ok(Vec::new());
         }
+        let script = Script::new(WRITE_SUBREDDITS_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(self.key("subreddit"))
+            .key(self.key("historical_deltas"))
+            .key(self.key("subreddit_updates"));
+        for (subreddit, event) in writes {
+            let stored = Subreddit { version: subreddit.version + 1, ..(*subreddit).clone() };
+            invocation
+                .arg(subreddit.id().as_str())
+                .arg(subreddit.version)
+                .arg(serde_json::to_string(&stored)?)
+                .arg(event);
+        }
+        let skipped: Vec<String> = invocation.invoke_async(&mut self.con()).await?;
+        for id in skipped.iter() {
+            warn!("Subreddit {id} changed since it was read, leaving it as it is.");
+        }
+        Ok(skipped.iter().map(|id| SubredditId::new(id)).collect())
+    }
+
+    /// Takes the lock `name` for `ttl`, unless someone else holds it.
+    pub async fn try_lock(&self, name: &str, token: &str, ttl: Duration) -> Result<bool> {
+        let set: Option<String> = redis::cmd("SET")
+            .arg(self.key(&format!("lock:{name}")))
+            .arg(token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut self.con()).await?;
+        Ok(set.is_some())
+    }
+
+    /// Extends a lock we hold. Returns false if it expired and was lost.
+    pub async fn renew_lock(&self, name: &str, token: &str, ttl: Duration) -> Result<bool> {
+        let renewed: i64 = Script::new(RENEW_LOCK_SCRIPT)
+            .key(self.key(&format!("lock:{name}")))
+            .arg(token)
+            .arg(ttl.as_millis() as u64)
+            .invoke_async(&mut self.con()).await?;
+        Ok(renewed == 1)
+    }
+
+    /// Gives up a lock, if we still hold it.
+    pub async fn release_lock(&self, name: &str, token: &str) -> Result<()> {
+        let _: i64 = Script::new(RELEASE_LOCK_SCRIPT)
+            .key(self.key(&format!("lock:{name}")))
+            .arg(token)
+            .invoke_async(&mut self.con()).await?;
         Ok(())
     }
 }
 
-pub async fn new_delta_stream(cli: &Cli) -> Result<impl TryStream<Ok = SubredditDelta, Error = anyhow::Error>> {
-    let mut pubsub = cli.new_redis_pubsub().await?;
-    pubsub.subscribe("subreddit_updates").await?;
+pub async fn new_delta_stream(redis_helper: &RedisHelper) -> Result<impl TryStream<Ok = SubredditDelta, Error = anyhow::Error>> {
+    let mut pubsub = redis_helper.new_pubsub().await?;
+    pubsub.subscribe(redis_helper.key("subreddit_updates")).await?;
     let s = pubsub.into_on_message();
     let s = s.map(|item: Msg| {
         let item: Msg = item;
@@ -115,13 +435,15 @@ pub async fn new_delta_stream(cli: &Cli) -> Result<impl TryStream<Ok = Subreddit
     Ok(s)
 }
 
-pub async fn new_reload_stream(cli: &Cli) -> Result<impl TryStream<Ok = (), Error = anyhow::Error>> {
-    let mut pubsub = cli.new_redis_pubsub().await?;
-    pubsub.subscribe("reload").await?;
+/// Reload requests on any of `channels`, as the channel they came in on.
+pub async fn new_reload_stream(redis_helper: &RedisHelper, channels: &[String]) -> Result<impl TryStream<Ok = String, Error = anyhow::Error>> {
+    let mut pubsub = redis_helper.new_pubsub().await?;
+    for channel in channels {
+        pubsub.subscribe(channel).await?;
+    }
     let s = pubsub.into_on_message();
     let s = s.map(|item: Msg| {
-        item.get_payload()?;
-        anyhow::Ok(())
+        anyhow::Ok(item.get_channel_name().to_string())
     });
     Ok(s)
-}
\ No newline at end of file
+}
@@ -0,0 +1,196 @@
+use std::str::FromStr;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::list_source::DEFAULT_SECTION;
+use crate::reddit::id::SubredditId;
+use crate::reddit::Subreddit;
+
+/// Subscriber size buckets, largest first, as (lower bound, section name).
+/// Named like the headings of the r/ModCoord list.
+pub const SIZE_BUCKETS: [(u64, &str); 13] = [
+    (40_000_000, "40+ million"),
+    (30_000_000, "30+ million"),
+    (20_000_000, "20+ million"),
+    (10_000_000, "10+ million"),
+    (5_000_000, "5+ million"),
+    (1_000_000, "1+ million"),
+    (500_000, "500k+"),
+    (250_000, "250k+"),
+    (100_000, "100k+"),
+    (50_000, "50k+"),
+    (5_000, "5k+"),
+    (1_000, "1k+"),
+    (0, "1k and below"),
+];
+
+/// Sections shown before the list updater first stores any, as the r/ModCoord list had them.
+pub const DEFAULT_SECTIONS: [&str; 14] = [
+    "40+ million",
+    "30+ million",
+    "20+ million",
+    "10+ million",
+    "5+ million",
+    "1+ million",
+    "500k+",
+    "250k+",
+    "100k+",
+    "50k+",
+    "5k+",
+    "5k and below",
+    "1k+",
+    "1k and below",
+];
+
+/// When to section subreddits by subscriber count instead of by their list source's headings.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+pub enum SizeSectioning {
+    /// Always use the source's sections.
+    Never,
+    /// Only for subreddits whose source has no sections for them.
+    Missing,
+    /// For every subreddit whose size is known.
+    Always,
+}
+
+/// A subscriber size bucket, given as `MIN=NAME`, where MIN may end in `k` or `m`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeBucket {
+    pub min: u64,
+    pub name: String,
+}
+
+impl FromStr for SizeBucket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (min, name) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("Size bucket must be MIN=NAME: {s}"))?;
+        let min = min.trim().to_lowercase();
+        let (digits, multiplier) = if let Some(d) = min.strip_suffix('m') {
+            (d, 1_000_000f64)
+        } else if let Some(d) = min.strip_suffix('k') {
+            (d, 1_000f64)
+        } else {
+            (min.as_str(), 1f64)
+        };
+        Ok(SizeBucket {
+            min: (digits.parse::<f64>()? * multiplier) as u64,
+            name: name.trim().to_string(),
+        })
+    }
+}
+
+pub fn default_size_buckets() -> Vec<SizeBucket> {
+    SIZE_BUCKETS.iter()
+        .map(|(min, name)| SizeBucket { min: *min, name: name.to_string() })
+        .collect()
+}
+
+/// Sorts buckets largest first, as `bucket_for` expects.
+pub fn sort_size_buckets(buckets: &mut [SizeBucket]) {
+    buckets.sort_by_key(|b| std::cmp::Reverse(b.min));
+}
+
+/// The bucket a subreddit with `subscribers` subscribers falls in. `buckets` must be sorted largest first.
+pub fn bucket_for(buckets: &[SizeBucket], subscribers: u64) -> Option<&SizeBucket> {
+    buckets.iter().find(|b| subscribers >= b.min)
+}
+
+/// Moves subreddits into the size bucket for their subscriber count, as `mode` asks, and returns
+/// the sections to show: the source's sections that still hold subreddits, then any buckets the source lacked.
+/// Subreddits whose size `subscribers` doesn't know yet keep their source's section.
+pub fn apply_size_sections(
+    mode: SizeSectioning,
+    buckets: &[SizeBucket],
+    sections: Vec<String>,
+    subreddits: &mut [Subreddit],
+    subscribers: impl Fn(&SubredditId) -> Option<u64>,
+) -> Vec<String> {
+    if mode == SizeSectioning::Never {
+        return sections;
+    }
+    for sub in subreddits.iter_mut() {
+        if mode == SizeSectioning::Missing && sub.section != DEFAULT_SECTION {
+            continue;
+        }
+        if let Some(bucket) = subscribers(&sub.id()).and_then(|n| bucket_for(buckets, n)) {
+            sub.section = bucket.name.clone();
+        }
+    }
+
+    let mut sections: Vec<String> = sections.into_iter()
+        .filter(|s| subreddits.iter().any(|sub| &sub.section == s))
+        .collect();
+    for bucket in buckets {
+        if !sections.contains(&bucket.name) && subreddits.iter().any(|sub| sub.section == bucket.name) {
+            sections.push(bucket.name.clone());
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::list_source::new_subreddit;
+
+    fn bucket(min: u64, name: &str) -> SizeBucket {
+        SizeBucket { min, name: name.to_string() }
+    }
+
+    #[test]
+    fn parses_size_buckets() {
+        assert_eq!("500=Tiny".parse::<SizeBucket>().unwrap(), bucket(500, "Tiny"));
+        assert_eq!("2.5k = Small".parse::<SizeBucket>().unwrap(), bucket(2_500, "Small"));
+        assert_eq!("1M=Huge".parse::<SizeBucket>().unwrap(), bucket(1_000_000, "Huge"));
+        assert!("Huge".parse::<SizeBucket>().is_err());
+        assert!("lots=Huge".parse::<SizeBucket>().is_err());
+    }
+
+    #[test]
+    fn sorts_buckets_largest_first() {
+        let mut buckets = vec![bucket(0, "Small"), bucket(1_000_000, "Huge"), bucket(1_000, "Medium")];
+        sort_size_buckets(&mut buckets);
+        assert_eq!(buckets.iter().map(|b| b.min).collect::<Vec<u64>>(), [1_000_000, 1_000, 0]);
+        assert_eq!(bucket_for(&buckets, 999).unwrap().name, "Small");
+        assert_eq!(bucket_for(&buckets, 1_000).unwrap().name, "Medium");
+    }
+
+    #[test]
+    fn default_sections_keep_every_heading() {
+        assert!(DEFAULT_SECTIONS.contains(&"5k and below"));
+        for (_, name) in SIZE_BUCKETS {
+            assert!(DEFAULT_SECTIONS.contains(&name), "{name} is missing");
+        }
+    }
+
+    fn sections_and_subs(mode: SizeSectioning) -> (Vec<String>, Vec<(String, String)>) {
+        let buckets = vec![bucket(1_000_000, "Huge"), bucket(0, "Small")];
+        let mut subs = vec![
+            new_subreddit("big", Some("Listed"), "test"),
+            new_subreddit("small", None, "test"),
+            new_subreddit("unknown", None, "test"),
+        ];
+        let sizes = [("big", 5_000_000), ("small", 10)];
+        let sections = apply_size_sections(mode, &buckets, vec!["Listed".to_string(), DEFAULT_SECTION.to_string()], &mut subs, |id| {
+            sizes.iter().find(|(name, _)| id.as_str() == *name).map(|(_, n)| *n)
+        });
+        (sections, subs.into_iter().map(|s| (s.name, s.section)).collect())
+    }
+
+    #[test]
+    fn applies_size_sections() {
+        let pair = |name: &str, section: &str| (name.to_string(), section.to_string());
+
+        let (sections, subs) = sections_and_subs(SizeSectioning::Never);
+        assert_eq!(sections, ["Listed", DEFAULT_SECTION]);
+        assert_eq!(subs, [pair("r/big", "Listed"), pair("r/small", DEFAULT_SECTION), pair("r/unknown", DEFAULT_SECTION)]);
+
+        let (sections, subs) = sections_and_subs(SizeSectioning::Missing);
+        assert_eq!(sections, ["Listed", DEFAULT_SECTION, "Small"]);
+        assert_eq!(subs, [pair("r/big", "Listed"), pair("r/small", "Small"), pair("r/unknown", DEFAULT_SECTION)]);
+
+        let (sections, subs) = sections_and_subs(SizeSectioning::Always);
+        assert_eq!(sections, [DEFAULT_SECTION, "Huge", "Small"]);
+        assert_eq!(subs, [pair("r/big", "Huge"), pair("r/small", "Small"), pair("r/unknown", DEFAULT_SECTION)]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap};
+use axum::Json;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+use crate::admin;
+use crate::reddit::{Subreddit, SubredditState};
+use crate::reddit::id::SubredditId;
+use crate::server::AppState;
+use crate::server::error::ServerError;
+
+#[derive(Deserialize)]
+pub struct PinRequest {
+    /// A state as the CLI takes it, e.g. `private`.
+    state: String,
+    reason: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct NoteRequest {
+    text: String,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleRequest {
+    dates: Vec<NaiveDate>,
+}
+
+/// The name of the admin whose bearer token the request carries.
+pub(super) fn authorize(state: &AppState, headers: &HeaderMap) -> Result<String, ServerError> {
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(ServerError::Unauthorized)?;
+    // Every token is compared, each in constant time, so timing doesn't tell how close a guess came.
+    let mut admin = None;
+    for t in &state.admin_tokens {
+        if bool::from(t.token.as_bytes().ct_eq(token.as_bytes())) {
+            admin = Some(t.name.clone());
+        }
+    }
+    admin.ok_or_else(|| {
+        warn!("Rejected admin request with an unknown token");
+        ServerError::Unauthorized
+    })
+}
+
+async fn load(state: &AppState, subreddit: &str) -> Result<Subreddit, ServerError> {
+    let id = SubredditId::from_str(subreddit).map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    state.redis_helper.get_subreddit(&id).await?
+        .ok_or(ServerError::NotFound)
+}
+
+/// Annotations are shown on the page, so a change drops its renders.
+async fn respond(state: &AppState, result: anyhow::Result<Subreddit>) -> Result<Json<Subreddit>, ServerError> {
+    let sub = result?;
+    state.render_cache.invalidate();
+    Ok(Json(sub))
+}
+
+pub async fn put_pin(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<PinRequest>,
+) -> Result<Json<Subreddit>, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let pinned = SubredditState::from_str(&req.state.to_lowercase()).map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    let sub = load(&state, &subreddit).await?;
+    respond(&state, admin::pin_state(&state.redis_helper, sub, pinned, &req.reason, req.expires_at, &author).await).await
+}
+
+pub async fn delete_pin(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Subreddit>, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let sub = load(&state, &subreddit).await?;
+    respond(&state, admin::unpin_state(&state.redis_helper, sub, &author).await).await
+}
+
+pub async fn post_note(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<NoteRequest>,
+) -> Result<Json<Subreddit>, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let sub = load(&state, &subreddit).await?;
+    respond(&state, admin::add_note(&state.redis_helper, sub, &req.text, &author).await).await
+}
+
+pub async fn delete_notes(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Subreddit>, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let sub = load(&state, &subreddit).await?;
+    respond(&state, admin::clear_notes(&state.redis_helper, sub, &author).await).await
+}
+
+pub async fn put_schedule(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<ScheduleRequest>,
+) -> Result<Json<Subreddit>, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let sub = load(&state, &subreddit).await?;
+    respond(&state, admin::set_schedule(&state.redis_helper, sub, req.dates, &author).await).await
+}
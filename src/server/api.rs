@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use axum::extract::State;
+use axum::Json;
+use crate::reddit::Subreddit;
+use crate::server::{visible_subreddits, AppState};
+use crate::server::error::ServerError;
+use crate::state_override::OverrideList;
+
+/// Every tracked subreddit with its state and metadata, sorted by name.
+pub async fn get_subreddits(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Subreddit>>, ServerError> {
+    let mut subreddits = visible_subreddits(&state.redis_helper, state.hide_withdrawn).await?;
+    subreddits.sort_by_key(|s| s.id());
+    Ok(Json(subreddits))
+}
+
+/// The state override lists the updater last applied, with their sources and validation reports.
+pub async fn get_overrides(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<OverrideList>>, ServerError> {
+    Ok(Json(state.redis_helper.get_state_overrides().await?))
+}
@@ -0,0 +1,62 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use crate::admin::{self, BlockAuditEntry};
+use crate::reddit::id::SubredditId;
+use crate::server::admin::authorize;
+use crate::server::AppState;
+use crate::server::error::ServerError;
+
+#[derive(Serialize)]
+pub struct Blocklist {
+    blocked: Vec<SubredditId>,
+    /// The latest changes, newest first.
+    audit: Vec<BlockAuditEntry>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct BlockRequest {
+    reason: Option<String>,
+}
+
+pub async fn get_blocklist(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Blocklist>, ServerError> {
+    authorize(&state, &headers)?;
+    let mut blocked = state.redis_helper.get_blocklist().await?
+        .into_iter()
+        .collect::<Vec<SubredditId>>();
+    blocked.sort();
+    let audit = state.redis_helper.get_block_audit(100).await?;
+    Ok(Json(Blocklist { blocked, audit }))
+}
+
+pub async fn put_block(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+    req: Option<Json<BlockRequest>>,
+) -> Result<StatusCode, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let id = SubredditId::from_str(&subreddit).map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    admin::set_blocked(&state.redis_helper, &id, true, req.reason.as_deref(), &author).await?;
+    state.render_cache.invalidate();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_block(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ServerError> {
+    let author = authorize(&state, &headers)?;
+    let id = SubredditId::from_str(&subreddit).map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    admin::set_blocked(&state.redis_helper, &id, false, None, &author).await?;
+    state.render_cache.invalidate();
+    Ok(StatusCode::NO_CONTENT)
+}
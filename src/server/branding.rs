@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+/// A link with the text to show for it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Link {
+    pub url: String,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Credit {
+    pub name: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    pub role: String,
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Social {
+    pub name: String,
+    pub url: String,
+    pub label: String,
+}
+
+/// The text and branding of the pages, read from a YAML file. Anything left out keeps the Reddark default.
+/// Fields documented as HTML are inserted as is.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Branding {
+    pub title: String,
+    /// Emoji used as the favicon and in the heading.
+    pub icon: String,
+    pub description: String,
+    pub google_site_verification: Option<String>,
+    /// A live stream of the page, advertised above the heading.
+    pub stream: Option<Link>,
+    /// HTML.
+    pub headline: String,
+    /// HTML.
+    pub contribute: Option<String>,
+    /// HTML, one paragraph each.
+    pub notes: Vec<String>,
+    pub credits: Vec<Credit>,
+    pub socials_title: String,
+    pub socials: Vec<Social>,
+    /// Other instances of the tracker, shown in case this one is down.
+    pub mirrors: Vec<String>,
+    /// HTML.
+    pub footer: String,
+}
+
+fn credit(name: &str, url: Option<&str>, role: &str, color: Option<&str>) -> Credit {
+    Credit {
+        name: name.to_string(),
+        url: url.map(|u| u.to_string()),
+        role: role.to_string(),
+        color: color.map(|c| c.to_string()),
+    }
+}
+
+fn social(name: &str, url: &str, label: &str) -> Social {
+    Social {
+        name: name.to_string(),
+        url: url.to_string(),
+        label: label.to_string(),
+    }
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Branding {
+            title: "Reddark".to_string(),
+            icon: "✊".to_string(),
+            description: "An open source website to watch subreddits going dark".to_string(),
+            google_site_verification: Some("U_FcRrVYPOtB9T7cs7klW5ioWQhkLbKyWH3H8AGK_es".to_string()),
+            stream: Some(Link {
+                url: "https://twitch.tv/reddark_247".to_string(),
+                label: "twitch.tv/reddark_247".to_string(),
+            }),
+            headline: r#"These subreddits went dark between June 12th and June 14th. Many are remaining that way indefinitely. Click <a
+            href="https://www.theverge.com/2023/6/5/23749188/reddit-subreddit-private-protest-api-changes-apollo-charges"
+            title="Major Reddit communities will go dark to protest threat to third-party apps">here</a>
+        to find out why."#.to_string(),
+            contribute: Some(r#"Like the website? Contribute <a href="https://github.com/reddark-remix/reddark-remix"
+                                        title="Browse the code source on GitHub">here!</a>"#.to_string()),
+            notes: vec![
+                "Due to slow rollout on reddit's side, some subreddits may flash public and private, if this is happening, it
+        means that the subreddit just changed their publicity type.".to_string(),
+                "Any subreddits which have just disabled posting will still be displayed as
+        \"restricted\".".to_string(),
+            ],
+            credits: vec![
+                credit("Tanza3D", Some("https://tanza.me"), "Director & original streamer", None),
+                credit("rewby", None, "Backend developer", None),
+                credit("MegaMix_Craft", Some("https://twitter.com/megamix_craft"), "Co-Director", None),
+                credit("Retiu", Some("https://linktr.ee/retiu"), "Sound design & Community Manager", None),
+                credit("jiniux", Some("https://github.com/jiniux"), "Server backend help (og reddark)", Some("Teal")),
+                credit("chromb", Some("https://chromb.uk"), "Community Manager", None),
+                credit("x3haven", None, "Chat moderator", None),
+                credit("theminingboss_", None, "Chat moderator", None),
+                credit("Nyaaori", Some("https://nyaaori.cat/"), "Chat meowderator & current streamer", Some("BlueViolet")),
+                credit("Durable", Some("https://twitter.com/doorible"), "Chat moderator", None),
+                credit("devnull", Some("https://www.twitch.tv/devnull9090"), "Official Reddark bot developer", None),
+                credit("MintKuro", Some("https://mints.gay/"), "Chat myaderator & playlist myanager", Some("MintCream")),
+            ],
+            socials_title: "Reddark socials".to_string(),
+            socials: vec![
+                social("Twitch", "https://twitch.tv/reddark_247", "twitch.tv/reddark_247"),
+                social("Twitter", "https://twitter.com/reddark_247", "twitter.com/reddark_247"),
+                social("Discord", "https://discord.gg/reddark", "discord.gg/reddark"),
+            ],
+            mirrors: vec!["https://reddark.io/".to_string()],
+            footer: r#"<p>Code Source is <a href="https://github.com/reddark-remix/reddark-remix/"
+                         title="Browse the code source on GitHub">here</a>
+        -
+        Join <a href="https://join-lemmy.org/instances" title="Join Lemmy instances">Lemmy</a> for less Reddit
+        shenanigans.</p>
+    <a href="https://untone.uk"><img src="https://id.untone.uk/public/img/branding/logo-text-light.svg"></a>"#.to_string(),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::any::Any;
+use axum::http::{header, HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use axum_template::engine::TeraError;
+use serde::Serialize;
+use tracing::{debug, error};
+
+/// Why a request failed. Answered as JSON, or as a small HTML page to browsers (see `error_pages`).
+#[derive(Debug)]
+pub enum ServerError {
+    BadRequest(String),
+    Unauthorized,
+    NotFound,
+    /// Redis can't be reached.
+    Backend(anyhow::Error),
+    /// A template failed to render.
+    Template(anyhow::Error),
+    /// Anything else that went wrong on our side.
+    Internal(anyhow::Error),
+    /// A handler panicked. The server keeps going.
+    Panic(String),
+}
+
+/// What `error_pages` needs to replace the JSON body, carried as a response extension.
+#[derive(Clone, Debug, Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl ServerError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ServerError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ServerError::NotFound => StatusCode::NOT_FOUND,
+            ServerError::Backend(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Template(_) | ServerError::Internal(_) | ServerError::Panic(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ServerError::BadRequest(_) => "bad_request",
+            ServerError::Unauthorized => "unauthorized",
+            ServerError::NotFound => "not_found",
+            ServerError::Backend(_) => "backend",
+            ServerError::Template(_) => "template",
+            ServerError::Internal(_) => "internal",
+            ServerError::Panic(_) => "panic",
+        }
+    }
+
+    /// Shown to the client. Internal details stay in the log.
+    fn message(&self) -> String {
+        match self {
+            ServerError::BadRequest(m) => m.clone(),
+            ServerError::Unauthorized => "A valid admin token is required.".to_string(),
+            ServerError::NotFound => "Nothing here.".to_string(),
+            ServerError::Backend(_) => "The tracker's data can't be reached right now. Try again shortly.".to_string(),
+            ServerError::Template(_) => "This page failed to render.".to_string(),
+            ServerError::Internal(_) | ServerError::Panic(_) => "Something went wrong.".to_string(),
+        }
+    }
+
+    /// For `CatchPanicLayer`.
+    pub fn from_panic(err: Box<dyn Any + Send + 'static>) -> Response {
+        let message = err.downcast_ref::<String>().cloned()
+            .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        ServerError::Panic(message).into_response()
+    }
+}
+
+/// Whether `e` comes from not reaching Redis, which is worth trying again shortly.
+fn is_unreachable(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|c| c.downcast_ref::<redis::RedisError>())
+        .any(|r| r.is_io_error() || r.is_connection_dropped() || r.is_connection_refusal() || r.is_timeout())
+}
+
+/// Errors from rendering are template errors, and failures to reach Redis are backend errors. Anything else,
+/// like data that doesn't parse, is a bug on our side.
+impl From<anyhow::Error> for ServerError {
+    fn from(e: anyhow::Error) -> Self {
+        if e.is::<TeraError>() {
+            ServerError::Template(e)
+        } else if is_unreachable(&e) {
+            ServerError::Backend(e)
+        } else {
+            ServerError::Internal(e)
+        }
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let kind = self.kind();
+        match &self {
+            ServerError::Backend(e) | ServerError::Template(e) | ServerError::Internal(e) => error!(status = status.as_u16(), kind, "Request failed: {e:#}"),
+            ServerError::Panic(m) => error!(status = status.as_u16(), kind, "Handler panicked: {m}"),
+            _ => debug!(status = status.as_u16(), kind, "Request rejected: {}", self.message()),
+        }
+        let body = ErrorBody { error: kind, message: self.message() };
+        let mut response = (status, Json(body.clone())).into_response();
+        response.extensions_mut().insert(body);
+        response
+    }
+}
+
+/// Swaps the JSON body of a `ServerError` for an HTML page when a browser made the request.
+pub async fn error_pages<B>(req: Request<B>, next: Next<B>) -> Response {
+    let wants_html = req.headers().get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/html"))
+        .unwrap_or(false);
+    let response = next.run(req).await;
+    if !wants_html {
+        return response;
+    }
+    let Some(body) = response.extensions().get::<ErrorBody>().cloned() else {
+        return response;
+    };
+    let status = response.status();
+    let mut page = (status, Html(format!(
+        "<!DOCTYPE html>\n<html><head><title>{status}</title></head><body><h1>{status}</h1><p>{}</p></body></html>\n",
+        tera::escape_html(&body.message),
+    ))).into_response();
+    page.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::*;
+
+    #[test]
+    fn maps_only_unreachable_redis_to_backend() {
+        let refused = redis::RedisError::from(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+        let e = anyhow::Error::from(refused).context("Unable to load subreddits");
+        assert_eq!(ServerError::from(e).status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let unparsable = serde_json::from_str::<u64>("nope").unwrap_err();
+        assert_eq!(ServerError::from(anyhow::Error::from(unparsable)).status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let type_error = redis::RedisError::from((redis::ErrorKind::TypeError, "wrong type"));
+        assert_eq!(ServerError::from(anyhow::Error::from(type_error)).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use crate::reddit::Subreddit;
+
+/// How many subreddits a filtered page shows at once.
+pub const PAGE_SIZE: usize = 500;
+
+/// Which subreddits a page shows, from its query string. Empty values, as sent by the search form, are left out.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filter {
+    /// Part of the name, in any case.
+    pub q: Option<String>,
+    pub section: Option<String>,
+    pub state: Option<String>,
+    /// `dark` or `light`.
+    pub only: Option<String>,
+    /// Starts at 1.
+    pub page: Option<usize>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+impl Filter {
+    /// Drops empty values and lowercases the ones compared without case, so equal filters share a cache entry.
+    pub fn normalized(&self) -> Self {
+        Filter {
+            q: non_empty(&self.q).map(|v| v.to_lowercase()),
+            section: non_empty(&self.section).map(|v| v.to_string()),
+            state: non_empty(&self.state).map(|v| v.to_lowercase()),
+            only: non_empty(&self.only).map(|v| v.to_lowercase()),
+            page: self.page.filter(|p| *p > 1),
+        }
+    }
+
+    /// Whether the page shows anything but the whole list at once.
+    pub fn is_active(&self) -> bool {
+        self.q.is_some() || self.section.is_some() || self.state.is_some() || self.only.is_some() || self.page.is_some()
+    }
+
+    pub fn matches(&self, sub: &Subreddit) -> bool {
+        if let Some(q) = &self.q {
+            if !sub.name.to_lowercase().contains(q) {
+                return false;
+            }
+        }
+        if let Some(section) = &self.section {
+            if sub.section != *section {
+                return false;
+            }
+        }
+        if let Some(state) = &self.state {
+            if !sub.state.to_string().eq_ignore_ascii_case(state) {
+                return false;
+            }
+        }
+        match self.only.as_deref() {
+            Some("dark") => sub.state.is_dark(),
+            Some("light") => sub.state.is_light(),
+            _ => true,
+        }
+    }
+
+    pub fn page(&self) -> usize {
+        self.page.unwrap_or(1)
+    }
+
+    /// The page of `items` the filter asks for, with its number and the number of pages.
+    /// Without a filter everything is on one page, and a page past the end shows the last one.
+    pub fn paginate<T>(&self, items: Vec<T>) -> (Vec<T>, usize, usize) {
+        if !self.is_active() {
+            return (items, 1, 1);
+        }
+        let pages = ((items.len() + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+        let page = self.page().min(pages);
+        let items = items.into_iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE).collect();
+        (items, page, pages)
+    }
+
+    /// The same filter on another page.
+    pub fn with_page(&self, page: usize) -> Self {
+        Filter { page: Some(page), ..self.clone() }.normalized()
+    }
+
+    /// The filter as a query string, without the `?`. Also used as the render cache key.
+    pub fn query_string(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        for (name, value) in [("q", &self.q), ("section", &self.section), ("state", &self.state), ("only", &self.only)] {
+            if let Some(value) = value {
+                query.append_pair(name, value);
+            }
+        }
+        if let Some(page) = self.page {
+            query.append_pair("page", &page.to_string());
+        }
+        query.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use crate::list_source::new_subreddit;
+    use crate::reddit::SubredditState;
+
+    fn try_parse(query: &str) -> Option<Filter> {
+        Query::<Filter>::try_from_uri(&format!("/?{query}").parse().unwrap()).ok().map(|Query(f)| f.normalized())
+    }
+
+    fn parse(query: &str) -> Filter {
+        try_parse(query).unwrap()
+    }
+
+    #[test]
+    fn normalizes_queries() {
+        let filter = parse("q=+Ask+&section=&state=PRIVATE&only=Dark&page=1");
+        assert_eq!(filter.q.as_deref(), Some("ask"));
+        assert_eq!(filter.section, None);
+        assert_eq!(filter.state.as_deref(), Some("private"));
+        assert_eq!(filter.only.as_deref(), Some("dark"));
+        assert_eq!(filter.page, None);
+        assert!(filter.is_active());
+        assert!(!parse("q=&section=&page=1").is_active());
+        assert!(try_parse("page=abc").is_none());
+    }
+
+    #[test]
+    fn round_trips_query_strings() {
+        let filter = parse("section=5k%2B&q=a%26b&page=3");
+        assert_eq!(filter.query_string(), "q=a%26b&section=5k%2B&page=3");
+        assert_eq!(parse(&filter.query_string()).query_string(), filter.query_string());
+        assert_eq!(filter.with_page(1).query_string(), "q=a%26b&section=5k%2B");
+    }
+
+    #[test]
+    fn matches_subreddits() {
+        let mut sub = new_subreddit("AskReddit", Some("40+ million"), "test");
+        sub.state = SubredditState::PRIVATE;
+        assert!(parse("q=askr").matches(&sub));
+        assert!(!parse("q=pics").matches(&sub));
+        assert!(parse("section=40%2B+million&state=private&only=dark").matches(&sub));
+        assert!(!parse("section=Other").matches(&sub));
+        assert!(!parse("only=light").matches(&sub));
+    }
+
+    #[test]
+    fn paginates() {
+        let items = (0..PAGE_SIZE * 2 + 1).collect::<Vec<usize>>();
+        let (page, number, pages) = parse("").paginate(items.clone());
+        assert_eq!((page.len(), number, pages), (items.len(), 1, 1));
+
+        let (page, number, pages) = parse("q=a&page=2").paginate(items.clone());
+        assert_eq!((page[0], page.len(), number, pages), (PAGE_SIZE, PAGE_SIZE, 2, 3));
+
+        let (page, number, _) = parse("q=a&page=9").paginate(items);
+        assert_eq!((page, number), (vec![PAGE_SIZE * 2], 3));
+
+        let (page, number, pages) = parse("q=a").paginate(Vec::<usize>::new());
+        assert_eq!((page.len(), number, pages), (0, 1, 1));
+    }
+}
@@ -1,64 +1,199 @@
 use std::future::Future;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
-use axum::routing::get;
+use anyhow::Context;
+use axum::middleware;
+use axum::response::Redirect;
+use axum::routing::{get, post, put};
 
 use axum::Server;
 use axum_prometheus::PrometheusMetricLayer;
 use axum_template::engine::Engine;
 use futures_util::{TryStreamExt, TryFutureExt};
 use tera::Tera;
-use tokio::sync::broadcast;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, watch};
+use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
-use tracing::info;
-use crate::reddit::SubredditState;
+use tracing::{info, warn};
+use crate::admin::AdminToken;
+use crate::reddit::{Subreddit, SubredditState};
 
 use crate::redis_helper::RedisHelper;
-use crate::server::model::PushMessage;
+use crate::server::branding::Branding;
+use crate::server::error::ServerError;
+use crate::server::model::{LiveSubreddit, PushMessage};
+use crate::server::render_cache::RenderCache;
+use crate::server::supervisor::{Supervisor, TaskHealthMap};
+use crate::tracker::Tracker;
 
+mod admin;
+mod api;
+mod branding;
+mod blocklist;
+mod error;
+mod filter;
 mod model;
+mod reload;
+mod render_cache;
 mod sse;
+mod supervisor;
 mod templ;
+mod widget;
+
+/// How long open requests get to finish once shutdown starts.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 
 // Type alias for our engine. For this example, we are using Handlebars
 pub type AppEngine = Engine<Tera>;
 
+/// Where the templates and branding are loaded from.
+#[derive(Clone, Debug)]
+pub struct SiteConfig {
+    pub template_dir: PathBuf,
+    pub branding: Option<PathBuf>,
+}
+
+/// The templates and branding, shared by every tracker and swapped out on reload.
+pub struct Site {
+    engine: AppEngine,
+    /// Names of the templates that can be served as views.
+    views: Vec<String>,
+    branding: Branding,
+}
+
+pub type SharedSite = Arc<RwLock<Site>>;
+
 pub struct AppState {
     broadcast_channel: broadcast::Sender<PushMessage>,
     redis_helper: RedisHelper,
-    engine: AppEngine,
+    site: SharedSite,
+    render_cache: RenderCache,
+    hide_withdrawn: bool,
+    /// Where this tracker's page is served, with a trailing slash.
+    base_path: String,
+    admin_tokens: Vec<AdminToken>,
+    /// Flips to true when the server starts shutting down.
+    shutdown: watch::Receiver<bool>,
 }
 
-async fn start_server(redis_helper: RedisHelper, broadcast_channel: broadcast::Sender<PushMessage>, listen: &str) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+/// The page, event stream and API of one tracker.
+fn tracker_router(state: Arc<AppState>) -> axum::Router {
+    axum::Router::new()
+        .route("/", get(templ::get_index))
+        .route("/view/:view", get(templ::get_view))
+        .route("/sse", get(sse::sse_handler))
+        .route("/widget/badge/:subreddit", get(widget::get_badge))
+        .route("/widget/progress.svg", get(widget::get_progress_svg))
+        .route("/widget/progress.png", get(widget::get_progress_png))
+        .route("/widget/counter", get(widget::get_counter))
+        .route("/api/subreddits.json", get(api::get_subreddits))
+        .route("/api/overrides.json", get(api::get_overrides))
+        .route("/api/admin/subreddits/:subreddit/pin", put(admin::put_pin).delete(admin::delete_pin))
+        .route("/api/admin/subreddits/:subreddit/notes", post(admin::post_note).delete(admin::delete_notes))
+        .route("/api/admin/subreddits/:subreddit/schedule", put(admin::put_schedule))
+        .route("/api/admin/blocklist", get(blocklist::get_blocklist))
+        .route("/api/admin/blocklist/:subreddit", put(blocklist::put_block).delete(blocklist::delete_block))
+        .with_state(state)
+}
+
+async fn start_server(root: Arc<AppState>, trackers: Vec<Arc<AppState>>, listen: &str, shutdown: watch::Receiver<bool>, health: TaskHealthMap) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+    let addr: SocketAddr = listen.parse().with_context(|| format!("Invalid listen address {listen}"))?;
     let serve_dir = ServeDir::new("public")
         .append_index_html_on_directories(true);
 
     let (prometheus_layer, metric_handle) = PrometheusMetricLayer::pair();
 
-    let shared_state = Arc::new(AppState {
-        broadcast_channel,
-        redis_helper,
-        engine: templ::make_app_engine().await?,
-    });
+    let mut app = tracker_router(root)
+        .fallback_service(serve_dir);
+    for state in trackers {
+        // The page loads its event stream relative to its own path, so it has to end in a slash.
+        let base_path = state.base_path.clone();
+        let redirect_to = base_path.clone();
+        app = app
+            .route(base_path.trim_end_matches('/'), get(|| async move { Redirect::permanent(&redirect_to) }))
+            .nest(&base_path, tracker_router(state));
+    }
 
-    let app = axum::Router::new()
-        .fallback_service(serve_dir)
-        .route("/", get(templ::get_index))
-        .route("/sse", get(sse::sse_handler))
-        .with_state(shared_state)
+    let app = app
         .route("/metrics", get(|| async move { metric_handle.render() }))
+        .route("/health", get(|| async move { supervisor::report(&health) }))
+        .layer(CatchPanicLayer::custom(ServerError::from_panic))
+        .layer(middleware::from_fn(error::error_pages))
         .layer(prometheus_layer)
         .layer(TraceLayer::new_for_http());
 
+    // Waits for open requests once shutdown starts. Event streams end by themselves then.
     Ok(
-        Server::bind(&listen.parse().unwrap())
+        Server::try_bind(&addr)?
             .serve(app.into_make_service())
+            .with_graceful_shutdown(shutdown_started(shutdown))
             .map_err(|e| anyhow::Error::from(e))
     )
 }
 
-async fn start_periodic_job(redis_helper: RedisHelper, broadcast_channel: broadcast::Sender<PushMessage>) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+/// Resolves once shutdown starts.
+pub async fn shutdown_started(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Starts shutdown on SIGTERM or Ctrl-C. Gives open requests `DRAIN_TIMEOUT` to finish, then returns to end the server.
+async fn start_shutdown_signal(shutdown: watch::Sender<bool>) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => info!("Got SIGTERM, shutting down..."),
+            _ = tokio::signal::ctrl_c() => info!("Got Ctrl-C, shutting down..."),
+        }
+        shutdown.send_replace(true);
+        tokio::time::sleep(DRAIN_TIMEOUT).await;
+        warn!("Requests still open after {DRAIN_TIMEOUT:?}, exiting anyway.");
+        anyhow::Ok(())
+    })
+}
+
+/// The subreddits to show: everything but blocked ones, and withdrawn ones if they are hidden.
+async fn visible_subreddits(redis_helper: &RedisHelper, hide_withdrawn: bool) -> anyhow::Result<Vec<Subreddit>> {
+    let blocklist = redis_helper.get_blocklist().await?;
+    let mut subreddits = redis_helper.get_current_state().await?;
+    subreddits.retain(|s| !blocklist.contains(&s.id()));
+    if hide_withdrawn {
+        subreddits.retain(|s| !s.is_withdrawn());
+    }
+    Ok(subreddits)
+}
+
+/// How many of the shown subreddits are dark, by `SubredditState::is_dark`.
+pub struct DarkCount {
+    pub total: usize,
+    pub dark: usize,
+}
+
+impl DarkCount {
+    pub fn of(subreddits: &[Subreddit]) -> Self {
+        DarkCount {
+            total: subreddits.len(),
+            dark: subreddits.iter().filter(|s| s.state.is_dark()).count(),
+        }
+    }
+
+    pub fn percent(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.dark as f32 / self.total as f32) * 100.0
+        }
+    }
+}
+
+async fn start_periodic_job(redis_helper: RedisHelper, broadcast_channel: broadcast::Sender<PushMessage>, hide_withdrawn: bool) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
     Ok(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(30));
         loop {
@@ -67,18 +202,19 @@ async fn start_periodic_job(redis_helper: RedisHelper, broadcast_channel: broadc
 
             // Fetch info
             let sections = redis_helper.get_sections().await?;
-            let mut subreddits = redis_helper.get_current_state().await?;
+            let mut subreddits = visible_subreddits(&redis_helper, hide_withdrawn).await?;
 
             subreddits.sort_by(|a, b| a.name.to_uppercase().partial_cmp(&b.name.to_uppercase()).unwrap());
 
             let message = PushMessage::CurrentStateUpdate {
                 sections,
-                subreddits,
+                subreddits: subreddits.iter().map(LiveSubreddit::from).collect(),
                 dark_states: SubredditState::dark_states(),
                 light_states: SubredditState::light_states(),
                 state_map: SubredditState::state_map(),
             };
-            broadcast_channel.send(message)?;
+            // Fails only while no client is connected.
+            let _ = broadcast_channel.send(message);
         }
         // Hint to type system
         #[allow(unreachable_code)]
@@ -86,63 +222,106 @@ async fn start_periodic_job(redis_helper: RedisHelper, broadcast_channel: broadc
     })
 }
 
-async fn start_pubsub(cli: &crate::Cli, broadcast_channel: broadcast::Sender<PushMessage>) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
-    let mut stream = crate::redis_helper::new_delta_stream(cli).await?;
+async fn start_pubsub(redis_helper: RedisHelper, broadcast_channel: broadcast::Sender<PushMessage>, render_cache: RenderCache) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+    let mut stream = crate::redis_helper::new_delta_stream(&redis_helper).await?;
+    // Deltas may have been missed while not subscribed.
+    render_cache.invalidate();
     Ok(async move {
         while let Some(delta) = stream.try_next().await? {
+            if redis_helper.get_blocklist().await?.contains(&delta.subreddit.id()) {
+                continue;
+            }
+            render_cache.invalidate();
             let message = PushMessage::Delta {
                 name: delta.subreddit.name.clone(),
                 section: delta.subreddit.section.clone(),
                 previous_state: delta.prev_state,
                 state: delta.subreddit.state,
             };
-            broadcast_channel.send(message)?;
+            // Fails only while no client is connected.
+            let _ = broadcast_channel.send(message);
         }
 
         anyhow::Ok(())
     })
 }
 
-async fn start_reload_pubsub(cli: &crate::Cli, broadcast_channel: broadcast::Sender<PushMessage>) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
-    let mut stream = crate::redis_helper::new_reload_stream(cli).await?;
-    Ok(async move {
-        while let Some(_) = stream.try_next().await? {
-            let message = PushMessage::Reload {};
-            broadcast_channel.send(message)?;
-        }
+/// Sets up a tracker's state, and the tasks feeding its event stream.
+fn start_tracker(redis_helper: RedisHelper, site: SharedSite, hide_withdrawn: bool, admin_tokens: &[AdminToken], shutdown: watch::Receiver<bool>, supervisor: &mut Supervisor) -> Arc<AppState> {
+    let tracker = redis_helper.tracker().cloned();
+    // No receiver is kept, so messages are only held for connected clients.
+    let (broadcast_channel, _) = broadcast::channel(4096);
+    let render_cache = RenderCache::default();
 
-        anyhow::Ok(())
+    {
+        let (redis_helper, broadcast_channel) = (redis_helper.clone(), broadcast_channel.clone());
+        supervisor.spawn(crate::tracker::key(tracker.as_ref(), "periodic"), move || {
+            start_periodic_job(redis_helper.clone(), broadcast_channel.clone(), hide_withdrawn).try_flatten()
+        });
+    }
+    {
+        let (redis_helper, broadcast_channel, render_cache) = (redis_helper.clone(), broadcast_channel.clone(), render_cache.clone());
+        supervisor.spawn(crate::tracker::key(tracker.as_ref(), "deltas"), move || {
+            start_pubsub(redis_helper.clone(), broadcast_channel.clone(), render_cache.clone()).try_flatten()
+        });
+    }
+
+    Arc::new(AppState {
+        broadcast_channel,
+        redis_helper,
+        site,
+        render_cache,
+        hide_withdrawn,
+        base_path: crate::tracker::base_path(tracker.as_ref()),
+        admin_tokens: admin_tokens.to_vec(),
+        shutdown,
     })
 }
 
-
-pub async fn server(cli: &crate::Cli, listen: &str) -> anyhow::Result<()> {
+pub async fn server(cli: &crate::Cli, listen: &str, hide_withdrawn: bool, trackers: &[Tracker], admin_tokens: &[AdminToken], site_config: SiteConfig, watch_templates: bool) -> anyhow::Result<()> {
     info!("Starting server");
     let redis_helper = RedisHelper::new(cli).await?;
+    let site: SharedSite = Arc::new(RwLock::new(templ::load_site(&site_config).await?));
 
-    let (broadcast_channel, _recv) = broadcast::channel(4096);
+    let (shutdown_sender, shutdown) = watch::channel(false);
 
-    let server = start_server(redis_helper.clone(), broadcast_channel.clone(), listen).await?;
-    let periodic_subreddits = start_periodic_job(redis_helper.clone(), broadcast_channel.clone()).await?;
-    let pubsub = start_pubsub(cli, broadcast_channel.clone()).await?;
-    let reload_pubsub = start_reload_pubsub(cli, broadcast_channel.clone()).await?;
+    // Background tasks are restarted when they fail. Only the HTTP server stopping stops the process.
+    let mut supervisor = Supervisor::default();
+    let root = start_tracker(redis_helper.clone(), site.clone(), hide_withdrawn, admin_tokens, shutdown.clone(), &mut supervisor);
+    let mut tracker_states = Vec::new();
+    for tracker in trackers {
+        info!("Serving tracker {tracker} at {}", crate::tracker::base_path(Some(tracker)));
+        tracker_states.push(start_tracker(redis_helper.for_tracker(Some(tracker.clone())), site.clone(), hide_withdrawn, admin_tokens, shutdown.clone(), &mut supervisor));
+    }
+
+    let reload_targets: Vec<(String, Arc<AppState>)> = std::iter::once(&root).chain(tracker_states.iter())
+        .map(|s| (crate::tracker::key(s.redis_helper.tracker(), "reload"), s.clone()))
+        .collect();
+    {
+        let (redis_helper, site_config) = (redis_helper.clone(), site_config.clone());
+        supervisor.spawn("reload".to_string(), move || {
+            reload::start_reload_pubsub(redis_helper.clone(), site.clone(), site_config.clone(), reload_targets.clone()).try_flatten()
+        });
+    }
+    {
+        let redis_helper = redis_helper.clone();
+        supervisor.spawn("sighup".to_string(), move || reload::start_sighup_reload(redis_helper.clone()).try_flatten());
+    }
+    if watch_templates {
+        let redis_helper = redis_helper.clone();
+        supervisor.spawn("template_watch".to_string(), move || {
+            reload::start_template_watch(redis_helper.clone(), site_config.clone()).try_flatten()
+        });
+    }
 
+    let shutdown_signal = start_shutdown_signal(shutdown_sender).await?;
+    let server = start_server(root, tracker_states, listen, shutdown, supervisor.health()).await?;
     tokio::select! {
-        val = pubsub => {
-            val?;
-        }
-        val = reload_pubsub => {
-            val?;
-        }
-        val = server => {
-            val?;
-        }
-        val = periodic_subreddits => {
-            val?;
-        }
+        result = server => result?,
+        result = shutdown_signal => result?,
     }
 
     info!("Exited!");
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -7,7 +7,7 @@ use crate::reddit::{Subreddit, SubredditState};
 pub enum PushMessage {
     CurrentStateUpdate {
         sections: Vec<String>,
-        subreddits: Vec<Subreddit>,
+        subreddits: Vec<LiveSubreddit>,
         dark_states: Vec<SubredditState>,
         light_states: Vec<SubredditState>,
         state_map: BTreeMap<SubredditState, String>,
@@ -22,3 +22,39 @@ pub enum PushMessage {
 
     },
 }
+
+/// What the live page shows of a subreddit. Sent for every subreddit with each state update, so kept small.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveSubreddit {
+    pub name: String,
+    pub section: String,
+    pub state: SubredditState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscribers: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_users: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<String>,
+}
+
+impl From<&Subreddit> for LiveSubreddit {
+    fn from(s: &Subreddit) -> Self {
+        LiveSubreddit {
+            name: s.name.clone(),
+            section: s.section.clone(),
+            state: s.state,
+            title: s.metadata.title.clone(),
+            subscribers: s.metadata.subscribers,
+            active_users: s.metadata.active_users,
+            pin_reason: s.annotations.pin.as_ref().map(|p| p.reason.clone()),
+            notes: s.annotations.notes.iter().map(|n| n.text.clone()).collect(),
+            schedule: s.annotations.schedule.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+}
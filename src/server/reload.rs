@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use futures_util::TryStreamExt;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+use crate::redis_helper::RedisHelper;
+use crate::server::model::PushMessage;
+use crate::server::{templ, AppState, SharedSite, SiteConfig};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Loads the templates and branding anew and drops pages rendered with the old ones.
+/// If the new ones don't load, the old ones stay in place.
+pub async fn reload_site(site: &SharedSite, config: &SiteConfig, trackers: &[(String, Arc<AppState>)]) -> anyhow::Result<()> {
+    let new_site = templ::load_site(config).await?;
+    *site.write().unwrap() = new_site;
+    for (_, state) in trackers {
+        state.render_cache.clear();
+    }
+    info!("Templates and branding reloaded.");
+    Ok(())
+}
+
+/// Reloads the templates and branding whenever a reload is published, then tells clients to reload their page.
+/// The `reload` channel reaches every tracker, `tracker:<name>:reload` just that one.
+pub async fn start_reload_pubsub(redis_helper: RedisHelper, site: SharedSite, config: SiteConfig, trackers: Vec<(String, Arc<AppState>)>) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+    let global = crate::tracker::key(None, "reload");
+    let mut channels = vec![global.clone()];
+    channels.extend(trackers.iter().map(|(c, _)| c.clone()).filter(|c| *c != global));
+    let mut stream = crate::redis_helper::new_reload_stream(&redis_helper, &channels).await?;
+    Ok(async move {
+        while let Some(channel) = stream.try_next().await? {
+            info!("Reload requested on {channel}");
+            if let Err(e) = reload_site(&site, &config, &trackers).await {
+                error!("Unable to reload templates or branding, keeping the old ones: {e}");
+                continue;
+            }
+            for (tracker_channel, state) in trackers.iter() {
+                if channel == global || channel == *tracker_channel {
+                    let _ = state.broadcast_channel.send(PushMessage::Reload {});
+                }
+            }
+        }
+
+        anyhow::Ok(())
+    })
+}
+
+fn latest_modification(path: &Path) -> std::io::Result<Option<SystemTime>> {
+    if !path.is_dir() {
+        return Ok(Some(std::fs::metadata(path)?.modified()?));
+    }
+    let mut latest = None;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let modified = if entry.file_type()?.is_dir() {
+            latest_modification(&entry.path())?
+        } else {
+            Some(entry.metadata()?.modified()?)
+        };
+        latest = latest.max(modified);
+    }
+    Ok(latest)
+}
+
+fn site_modification(config: &SiteConfig) -> std::io::Result<Option<SystemTime>> {
+    let templates = latest_modification(&config.template_dir)?;
+    let branding = config.branding.as_deref().map(latest_modification).transpose()?.flatten();
+    Ok(templates.max(branding))
+}
+
+/// Publishes a reload whenever a template or the branding file changes.
+pub async fn start_template_watch(redis_helper: RedisHelper, config: SiteConfig) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+    let mut last = site_modification(&config)?;
+    Ok(async move {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let modified = match site_modification(&config) {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!("Unable to check templates for changes: {e}");
+                    continue;
+                }
+            };
+            if modified != last {
+                info!("Templates or branding changed, requesting reload...");
+                last = modified;
+                redis_helper.publish_reload().await?;
+            }
+        }
+        // Hint to type system
+        #[allow(unreachable_code)]
+        anyhow::Ok(())
+    })
+}
+
+/// Publishes a reload when the server gets SIGHUP.
+pub async fn start_sighup_reload(redis_helper: RedisHelper) -> anyhow::Result<impl Future<Output=anyhow::Result<()>>> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    Ok(async move {
+        while hangup.recv().await.is_some() {
+            info!("Got SIGHUP, requesting reload...");
+            redis_helper.publish_reload().await?;
+        }
+
+        anyhow::Ok(())
+    })
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::body::Bytes;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tracing::warn;
+
+/// How long a render is used for when no delta comes in. Subscriber counts and the like change without one.
+const MAX_AGE: Duration = Duration::from_secs(30);
+/// Renders kept at most, across every view and filter.
+const MAX_ENTRIES: usize = 1000;
+
+/// A rendered page, with what's needed to answer conditional and compressed requests.
+pub struct Rendered {
+    pub html: Bytes,
+    pub gzip: Bytes,
+    /// Weak, so it holds for both the plain and the compressed body.
+    pub etag: String,
+}
+
+impl Rendered {
+    fn new(html: String) -> anyhow::Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        html.hash(&mut hasher);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(html.as_bytes())?;
+        Ok(Rendered {
+            etag: format!("W/\"{:016x}\"", hasher.finish()),
+            gzip: Bytes::from(encoder.finish()?),
+            html: Bytes::from(html),
+        })
+    }
+}
+
+struct Entry {
+    rendered: Arc<Rendered>,
+    rendered_at: Instant,
+    generation: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Bumped by every invalidation. Entries from an older generation are only served when rendering fails.
+    generation: u64,
+    entries: HashMap<String, Entry>,
+    /// Held while a key renders, so a burst of requests after an invalidation renders it once.
+    rendering: HashMap<String, Arc<tokio::sync::Mutex<()>>>,
+}
+
+impl Inner {
+    fn fresh(&self, key: &str) -> Option<Arc<Rendered>> {
+        self.entries.get(key)
+            .filter(|e| e.generation == self.generation && e.rendered_at.elapsed() < MAX_AGE)
+            .map(|e| e.rendered.clone())
+    }
+}
+
+/// Rendered pages of one tracker, dropped when a delta comes in or the templates are reloaded.
+/// When rendering fails, the last good render is served instead, marked as stale.
+#[derive(Clone, Default)]
+pub struct RenderCache {
+    // Only held for lookups and inserts, never while rendering.
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl RenderCache {
+    /// Marks every render as outdated. They are kept to fall back on.
+    pub fn invalidate(&self) {
+        self.inner.lock().unwrap().generation += 1;
+    }
+
+    /// Drops every render, for when the old ones can't be served any more.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+    }
+
+    /// The render for `key`, rendering it anew if it is outdated. The flag is true if it is a stale fallback.
+    /// Different keys render concurrently.
+    pub async fn get_or_render<F, Fut>(&self, key: String, render: F) -> anyhow::Result<(Arc<Rendered>, bool)>
+        where F: FnOnce() -> Fut,
+              Fut: Future<Output=anyhow::Result<String>> {
+        let key_lock = {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(rendered) = inner.fresh(&key) {
+                return Ok((rendered, false));
+            }
+            inner.rendering.entry(key.clone()).or_default().clone()
+        };
+        let _rendering = key_lock.lock().await;
+
+        // Whoever held the key before may have rendered it already.
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(rendered) = inner.fresh(&key) {
+                if Arc::strong_count(&key_lock) == 2 {
+                    inner.rendering.remove(&key);
+                }
+                return Ok((rendered, false));
+            }
+            inner.generation
+        };
+
+        let result = render().await.and_then(Rendered::new);
+
+        let mut inner = self.inner.lock().unwrap();
+        // Nobody else waits for the key, and anyone coming later finds the render below.
+        if Arc::strong_count(&key_lock) == 2 {
+            inner.rendering.remove(&key);
+        }
+        let rendered = match result {
+            Ok(rendered) => Arc::new(rendered),
+            Err(e) => {
+                return match inner.entries.get(&key) {
+                    Some(entry) => {
+                        warn!("Unable to render {key}, serving the render from {:?} ago: {e:#}", entry.rendered_at.elapsed());
+                        Ok((entry.rendered.clone(), true))
+                    }
+                    None => Err(e),
+                };
+            }
+        };
+
+        if inner.entries.len() >= MAX_ENTRIES && !inner.entries.contains_key(&key) {
+            let oldest = inner.entries.iter()
+                .min_by_key(|(_, e)| e.rendered_at)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(key, Entry {
+            rendered: rendered.clone(),
+            rendered_at: Instant::now(),
+            generation,
+        });
+        Ok((rendered, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn render(cache: &RenderCache, renders: &AtomicUsize, result: anyhow::Result<&str>) -> anyhow::Result<(Arc<Rendered>, bool)> {
+        cache.get_or_render("index?".to_string(), || async {
+            renders.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            result.map(|html| html.to_string())
+        }).await
+    }
+
+    #[tokio::test]
+    async fn renders_once_until_invalidated() {
+        let cache = RenderCache::default();
+        let renders = AtomicUsize::new(0);
+        let (first, second) = tokio::join!(render(&cache, &renders, Ok("one")), render(&cache, &renders, Ok("one")));
+        assert_eq!(first.unwrap().0.etag, second.unwrap().0.etag);
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+
+        cache.invalidate();
+        let (rendered, stale) = render(&cache, &renders, Ok("two")).await.unwrap();
+        assert_eq!((&rendered.html[..], stale), (&b"two"[..], false));
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_render_when_rendering_fails() {
+        let cache = RenderCache::default();
+        let renders = AtomicUsize::new(0);
+        let (good, _) = render(&cache, &renders, Ok("good")).await.unwrap();
+
+        cache.invalidate();
+        let (rendered, stale) = render(&cache, &renders, Err(anyhow::anyhow!("redis is down"))).await.unwrap();
+        assert!(stale);
+        assert_eq!(rendered.etag, good.etag);
+
+        // The stale render is kept until a render succeeds.
+        let (rendered, stale) = render(&cache, &renders, Ok("back")).await.unwrap();
+        assert_eq!((&rendered.html[..], stale), (&b"back"[..], false));
+
+        cache.clear();
+        assert!(render(&cache, &renders, Err(anyhow::anyhow!("redis is down"))).await.is_err());
+    }
+}
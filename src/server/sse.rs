@@ -7,7 +7,7 @@ use futures_util::Stream;
 use futures_util::StreamExt;
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
-use crate::server::AppState;
+use crate::server::{shutdown_started, AppState};
 use crate::server::model::PushMessage;
 
 pub async fn sse_handler(
@@ -22,7 +22,9 @@ pub async fn sse_handler(
             let message = message?;
             let data = serde_json::to_string(&message)?;
             Ok(Event::default().data(data))
-        });
+        })
+        // Ends the stream on shutdown, so the server doesn't wait on it. Clients reconnect by themselves.
+        .take_until(shutdown_started(state.shutdown.clone()));
 
     Sse::new(stream).keep_alive(
         axum::response::sse::KeepAlive::new()
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Wait before the first restart. Doubles with every failure in a row.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A task running this long is taken to have recovered, and its backoff starts over.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    /// Stopped, waiting out its backoff.
+    Restarting,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskHealth {
+    pub state: TaskState,
+    pub restarts: u32,
+    /// When the current run started.
+    pub started_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+pub type TaskHealthMap = Arc<RwLock<BTreeMap<String, TaskHealth>>>;
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    healthy: bool,
+    tasks: BTreeMap<String, TaskHealth>,
+}
+
+/// Runs the server's background tasks, restarting any that stop, fail or panic.
+/// Tasks are aborted when the supervisor is dropped.
+#[derive(Default)]
+pub struct Supervisor {
+    health: TaskHealthMap,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    /// Starts a task. `task` is called again for every restart, so it should set up its own connections and subscriptions.
+    /// A task is not expected to end: ending without an error counts as a failure too.
+    pub fn spawn<F, Fut>(&mut self, name: String, mut task: F)
+        where F: FnMut() -> Fut + Send + 'static,
+              Fut: Future<Output=anyhow::Result<()>> + Send + 'static {
+        let health = self.health.clone();
+        self.handles.push(tokio::spawn(async move {
+            let mut backoff = MIN_BACKOFF;
+            let mut restarts = 0;
+            loop {
+                let started = Instant::now();
+                update(&health, &name, |h| {
+                    h.state = TaskState::Running;
+                    h.started_at = Utc::now();
+                });
+                let error = match AssertUnwindSafe(task()).catch_unwind().await {
+                    Ok(Ok(())) => "ended".to_string(),
+                    Ok(Err(e)) => format!("{e:#}"),
+                    Err(_) => "panicked".to_string(),
+                };
+
+                if started.elapsed() >= STABLE_AFTER {
+                    backoff = MIN_BACKOFF;
+                }
+                restarts += 1;
+                warn!(task = name.as_str(), restarts, "Task {name} stopped, restarting in {backoff:?}: {error}");
+                update(&health, &name, |h| {
+                    h.state = TaskState::Restarting;
+                    h.restarts = restarts;
+                    h.last_error = Some(error);
+                    h.last_error_at = Some(Utc::now());
+                });
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                info!(task = name.as_str(), "Restarting task {name}");
+            }
+        }));
+    }
+
+    pub fn health(&self) -> TaskHealthMap {
+        self.health.clone()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+fn update(health: &TaskHealthMap, name: &str, f: impl FnOnce(&mut TaskHealth)) {
+    let mut health = health.write().unwrap();
+    let entry = health.entry(name.to_string()).or_insert_with(|| TaskHealth {
+        state: TaskState::Running,
+        restarts: 0,
+        started_at: Utc::now(),
+        last_error: None,
+        last_error_at: None,
+    });
+    f(entry);
+}
+
+/// Every task with its state. 503 while any of them is restarting.
+pub fn report(health: &TaskHealthMap) -> (StatusCode, Json<HealthReport>) {
+    let tasks = health.read().unwrap().clone();
+    let healthy = tasks.values().all(|t| t.state == TaskState::Running);
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(HealthReport { healthy, tasks }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn restarts_panicking_task() {
+        let mut supervisor = Supervisor::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let task_runs = runs.clone();
+        supervisor.spawn("flaky".to_string(), move || {
+            let run = task_runs.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run == 0 {
+                    panic!("first run fails");
+                }
+                std::future::pending::<()>().await;
+                Ok(())
+            }
+        });
+        let health = supervisor.health();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (status, Json(health_report)) = report(&health);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let task = &health_report.tasks["flaky"];
+        assert_eq!((task.state, task.restarts, task.last_error.as_deref()), (TaskState::Restarting, 1, Some("panicked")));
+
+        tokio::time::sleep(MIN_BACKOFF + Duration::from_millis(100)).await;
+        let (status, Json(health_report)) = report(&health);
+        assert_eq!(status, StatusCode::OK);
+        assert!(health_report.healthy);
+        let task = &health_report.tasks["flaky"];
+        assert_eq!((task.state, task.restarts, task.last_error.as_deref()), (TaskState::Running, 1, Some("panicked")));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
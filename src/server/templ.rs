@@ -1,19 +1,34 @@
 use std::collections::BTreeMap;
 use std::sync::Arc;
-use axum::extract::State;
-use axum::response::{Html, IntoResponse};
+use anyhow::Context;
+use axum::extract::{Path, Query, State};
+use axum::extract::rejection::QueryRejection;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum_template::engine::Engine;
 use axum_template::TemplateEngine;
-use cached::proc_macro::cached;
 use serde::Serialize;
 use tera::Tera;
-use crate::reddit::{SubredditDelta, SubredditState};
-use crate::server::{AppEngine, AppState};
+use crate::reddit::{Subreddit, SubredditDelta, SubredditState};
+use crate::server::{visible_subreddits, AppState, DarkCount, Site, SiteConfig};
+use crate::server::branding::Branding;
+use crate::server::error::ServerError;
+use crate::sections::SizeBucket;
+use crate::server::filter::Filter;
+use crate::server::render_cache::Rendered;
 
 #[derive(Serialize, Debug)]
 struct ParamSubreddit {
     name: String,
     state: String,
+    withdrawn: bool,
+    title: Option<String>,
+    subscribers: Option<u64>,
+    active_users: Option<u64>,
+    size_bucket: Option<String>,
+    pin_reason: Option<String>,
+    notes: Vec<String>,
+    schedule: Vec<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -27,50 +42,217 @@ struct Params {
     dark_states: Vec<SubredditState>,
     light_states: Vec<SubredditState>,
     state_map: BTreeMap<SubredditState, String>,
+    branding: Branding,
+    base_path: String,
+    /// Every section, for the search form. `sections` only has the ones on this page.
+    all_sections: Vec<String>,
+    filter: Filter,
+    filtered: bool,
+    /// How many subreddits match the filter, on any page.
+    matched_subs: usize,
+    page: usize,
+    pages: usize,
+    prev_url: Option<String>,
+    next_url: Option<String>,
 }
 
-pub async fn make_app_engine() -> anyhow::Result<AppEngine> {
-    let mut tera = Tera::default();
-    tera.add_template_file("templates/index.html", Some("index"))?;
-    Ok(Engine::from(tera))
+/// Loads every template in the template directory, and the branding file if there is one.
+pub async fn load_site(config: &SiteConfig) -> anyhow::Result<Site> {
+    let tera = Tera::new(&format!("{}/**/*.html", config.template_dir.display()))?;
+    let views = tera.get_template_names()
+        .filter_map(|n| n.strip_suffix(".html"))
+        .filter(|n| !n.starts_with('_'))
+        .map(|n| n.to_string())
+        .collect();
+    let branding = match &config.branding {
+        Some(path) => {
+            let text = tokio::fs::read_to_string(path).await
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            serde_yaml::from_str(&text)?
+        }
+        None => Branding::default(),
+    };
+    Ok(Site {
+        engine: Engine::from(tera),
+        views,
+        branding,
+    })
+}
+
+fn param_subreddit(s: &Subreddit, size_buckets: &[SizeBucket]) -> ParamSubreddit {
+    ParamSubreddit {
+        name: s.name.clone(),
+        state: s.state.to_string(),
+        withdrawn: s.is_withdrawn(),
+        title: s.metadata.title.clone(),
+        subscribers: s.metadata.subscribers,
+        active_users: s.metadata.active_users,
+        size_bucket: s.metadata.size_bucket(size_buckets),
+        pin_reason: s.annotations.pin.as_ref().map(|p| p.reason.clone()),
+        notes: s.annotations.notes.iter().map(|n| n.text.clone()).collect(),
+        schedule: s.annotations.schedule.iter().map(|d| d.to_string()).collect(),
+    }
 }
 
-#[cached(time = 30, sync_writes = true, key = "String", convert = r#"{ "A".to_string() }"#)]
-async fn render_index(state: Arc<AppState>) -> String {
-    let subs = state.redis_helper.get_current_state().await.unwrap();
-    let dark_subs = subs.iter().filter(|s| s.state.is_dark()).count();
-    let total_subs = subs.len();
-    let sections = state.redis_helper.get_sections().await.unwrap();
-    let history = state.redis_helper.get_hist_delta().await.unwrap_or_else(|_| Vec::new());
+async fn render_view(state: &AppState, view: &str, filter: Filter) -> anyhow::Result<String> {
+    let subs = visible_subreddits(&state.redis_helper, state.hide_withdrawn).await?;
+    let blocklist = state.redis_helper.get_blocklist().await?;
+    let count = DarkCount::of(&subs);
+    let sections = state.redis_helper.get_sections().await?;
+    let size_buckets = state.redis_helper.get_size_buckets().await?;
+    let mut history = state.redis_helper.get_hist_delta().await.unwrap_or_else(|_| Vec::new());
+    history.retain(|d| !blocklist.contains(&d.subreddit.id()));
+
+    // Sorted the way the page lists them, so pages split the list where a reader would expect.
+    let mut matched = subs.iter()
+        .filter(|s| filter.matches(s))
+        .filter_map(|s| sections.iter().position(|section| *section == s.section).map(|i| (i, s)))
+        .collect::<Vec<(usize, &Subreddit)>>();
+    matched.sort_by_key(|(i, s)| (*i, s.name.to_uppercase()));
+    let matched_subs = matched.len();
+    let (matched, page, pages) = filter.paginate(matched);
+
+    let mut subreddits: BTreeMap<String, Vec<ParamSubreddit>> = sections.iter().map(|s| (s.clone(), Vec::new())).collect();
+    for (_, s) in matched {
+        subreddits.entry(s.section.clone()).or_default().push(param_subreddit(s, &size_buckets));
+    }
+    let page_url = |page: usize| format!("{}?{}", state.base_path, filter.with_page(page).query_string());
     let params = Params {
-        perc_subs: format!("{:.2}", (dark_subs as f32 / total_subs as f32) * 100.0),
-        total_subs,
-        dark_subs,
+        perc_subs: format!("{:.2}", count.percent()),
+        total_subs: count.total,
+        dark_subs: count.dark,
         history,
         dark_states: SubredditState::dark_states(),
         light_states: SubredditState::light_states(),
-        sections: sections.clone(),
-        subreddits: sections.into_iter()
-            .map(|section| {
-                let mut fsubs =  subs.iter()
-                    .filter(|s| s.section == section)
-                    .map(|s| ParamSubreddit {
-                        name: s.name.clone(),
-                        state: s.state.to_string(),
-                    })
-                    .collect::<Vec<ParamSubreddit>>();
-                fsubs.sort_by(|a, b| a.name.to_uppercase().partial_cmp(&b.name.to_uppercase()).unwrap());
-                (section.clone(), fsubs)
-            })
-            .collect(),
+        // A filtered page only heads the sections it has subreddits in.
+        sections: if filter.is_active() {
+            sections.iter().filter(|s| !subreddits[*s].is_empty()).cloned().collect()
+        } else {
+            sections.clone()
+        },
+        all_sections: sections,
+        subreddits,
         state_map: SubredditState::state_map(),
+        branding: state.site.read().unwrap().branding.clone(),
+        base_path: state.base_path.clone(),
+        filtered: filter.is_active(),
+        matched_subs,
+        page,
+        pages,
+        prev_url: (page > 1).then(|| page_url(page - 1)),
+        next_url: (page < pages).then(|| page_url(page + 1)),
+        filter,
     };
 
-    state.engine.render("index", params).unwrap()
+    Ok(state.site.read().unwrap().engine.render(&format!("{view}.html"), params)?)
+}
+
+/// Serves a view from the render cache.
+async fn respond(state: &AppState, view: &str, filter: Filter, headers: &HeaderMap) -> Result<Response, ServerError> {
+    let filter = filter.normalized();
+    let key = format!("{view}?{}", filter.query_string());
+    let (rendered, stale) = state.render_cache.get_or_render(key, || render_view(state, view, filter)).await?;
+    Ok(cached_response(&rendered, stale, headers))
+}
+
+/// Answers `If-None-Match` with 304, and sends the gzipped render to clients accepting it. A stale render, served
+/// because rendering failed, carries a `Warning` header.
+fn cached_response(rendered: &Rendered, stale: bool, headers: &HeaderMap) -> Response {
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ETAG, HeaderValue::from_str(&rendered.etag).unwrap());
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if stale {
+        response_headers.insert(header::WARNING, HeaderValue::from_static("110 - \"Response is Stale\""));
+    }
+
+    let not_modified = headers.get_all(header::IF_NONE_MATCH).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| tag.trim() == "*" || tag.trim().trim_start_matches("W/") == rendered.etag.trim_start_matches("W/"));
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    let gzip = headers.get_all(header::ACCEPT_ENCODING).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|e| e.trim().split(';').next() == Some("gzip"));
+    if gzip {
+        response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        (response_headers, rendered.gzip.clone()).into_response()
+    } else {
+        (response_headers, rendered.html.clone()).into_response()
+    }
+}
+
+/// A query string that doesn't parse, like `?page=abc`, gets the usual error page.
+fn parse_filter(filter: Result<Query<Filter>, QueryRejection>) -> Result<Filter, ServerError> {
+    filter.map(|Query(filter)| filter).map_err(|e| ServerError::BadRequest(e.body_text()))
 }
 
 pub async fn get_index(
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    Html(render_index(state).await).into_response()
-}
\ No newline at end of file
+    filter: Result<Query<Filter>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    respond(&state, "index", parse_filter(filter)?, &headers).await
+}
+
+/// Any template in the template directory, by name without `.html`. Names starting with `_` are left out,
+/// for templates other templates include.
+pub async fn get_view(
+    State(state): State<Arc<AppState>>,
+    Path(view): Path<String>,
+    filter: Result<Query<Filter>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    if !state.site.read().unwrap().views.contains(&view) {
+        return Err(ServerError::NotFound);
+    }
+    respond(&state, &view, parse_filter(filter)?, &headers).await
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::render_cache::RenderCache;
+
+    async fn rendered(html: &str) -> Arc<Rendered> {
+        let html = html.to_string();
+        RenderCache::default().get_or_render("index?".to_string(), || async { Ok(html) }).await.unwrap().0
+    }
+
+    fn request(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs.iter().map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap())).collect()
+    }
+
+    #[tokio::test]
+    async fn answers_matching_etags_with_not_modified() {
+        let rendered = rendered("<html>page</html>").await;
+        let strong = rendered.etag.trim_start_matches("W/").to_string();
+        for tag in [rendered.etag.as_str(), strong.as_str(), "\"other\", *", &format!("\"other\", {}", rendered.etag)] {
+            let response = cached_response(&rendered, false, &request(&[(header::IF_NONE_MATCH, tag)]));
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{tag}");
+            assert_eq!(response.headers()[header::ETAG], rendered.etag.as_str());
+        }
+
+        let response = cached_response(&rendered, false, &request(&[(header::IF_NONE_MATCH, "W/\"other\"")]));
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, rendered.html);
+    }
+
+    #[tokio::test]
+    async fn sends_gzip_and_marks_stale_renders() {
+        let rendered = rendered("<html>page</html>").await;
+        let response = cached_response(&rendered, true, &request(&[(header::ACCEPT_ENCODING, "br, gzip;q=0.8")]));
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(response.headers()[header::WARNING].to_str().unwrap().starts_with("110"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, rendered.gzip);
+
+        let response = cached_response(&rendered, false, &HeaderMap::new());
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert!(response.headers().get(header::WARNING).is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use crate::reddit::id::SubredditId;
+use crate::server::{visible_subreddits, AppState, DarkCount};
+use crate::server::error::ServerError;
+
+const DARK_COLOR: &str = "#00b377";
+const LIGHT_COLOR: &str = "#e05d44";
+const UNKNOWN_COLOR: &str = "#9f9f9f";
+const LABEL_COLOR: &str = "#555";
+
+const BAR_WIDTH: u32 = 300;
+const BAR_HEIGHT: u32 = 20;
+const BAR_FILL: [u8; 4] = [0x00, 0xff, 0xaa, 0xff];
+const BAR_BACKGROUND: [u8; 4] = [0x22, 0x22, 0x22, 0xff];
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn image(content_type: &'static str, body: impl IntoResponse) -> Response {
+    (
+        [(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "max-age=30")],
+        body,
+    ).into_response()
+}
+
+async fn dark_count(state: &AppState) -> Result<DarkCount, ServerError> {
+    let subreddits = visible_subreddits(&state.redis_helper, state.hide_withdrawn).await?;
+    Ok(DarkCount::of(&subreddits))
+}
+
+/// A two-part badge, like the ones on READMEs. Text widths are estimated, which is close enough for short labels.
+fn badge_svg(label: &str, value: &str, color: &str) -> String {
+    let label_width = label.len() * 7 + 10;
+    let value_width = value.len() * 7 + 10;
+    let width = label_width + value_width;
+    let (label, value) = (escape_xml(label), escape_xml(value));
+    format!(r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}">
+<title>{label}: {value}</title>
+<rect width="{label_width}" height="20" fill="{LABEL_COLOR}"/>
+<rect x="{label_width}" width="{value_width}" height="20" fill="{color}"/>
+<g fill="#fff" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11" text-anchor="middle">
+<text x="{}" y="14">{label}</text>
+<text x="{}" y="14">{value}</text>
+</g>
+</svg>"##, label_width / 2, label_width + value_width / 2)
+}
+
+/// A badge with one subreddit's current state.
+pub async fn get_badge(
+    State(state): State<Arc<AppState>>,
+    Path(subreddit): Path<String>,
+) -> Result<Response, ServerError> {
+    let id = SubredditId::from_str(subreddit.trim_end_matches(".svg")).map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    if state.redis_helper.get_blocklist().await?.contains(&id) {
+        return Err(ServerError::NotFound);
+    }
+    let sub = state.redis_helper.get_subreddit(&id).await?
+        .filter(|s| !(state.hide_withdrawn && s.is_withdrawn()))
+        .ok_or(ServerError::NotFound)?;
+    let color = if sub.state.is_dark() {
+        DARK_COLOR
+    } else if sub.state == crate::reddit::SubredditState::UNKNOWN {
+        UNKNOWN_COLOR
+    } else {
+        LIGHT_COLOR
+    };
+    Ok(image("image/svg+xml", badge_svg(&id.prefixed(), &sub.state.to_string(), color)))
+}
+
+/// A bar filled to the share of dark subreddits, with the numbers on it.
+pub async fn get_progress_svg(
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ServerError> {
+    let count = dark_count(&state).await?;
+    let filled = (BAR_WIDTH as f32 * count.percent() / 100.0).round() as u32;
+    let text = format!("{}/{} dark ({:.2}%)", count.dark, count.total, count.percent());
+    let svg = format!(r##"<svg xmlns="http://www.w3.org/2000/svg" width="{BAR_WIDTH}" height="{BAR_HEIGHT}" role="img" aria-label="{text}">
+<title>{text}</title>
+<rect width="{BAR_WIDTH}" height="{BAR_HEIGHT}" fill="#222"/>
+<rect width="{filled}" height="{BAR_HEIGHT}" fill="#00ffaa"/>
+<text x="{}" y="14" fill="#fff" stroke="#000" stroke-width="0.3" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11" text-anchor="middle">{text}</text>
+</svg>"##, BAR_WIDTH / 2);
+    Ok(image("image/svg+xml", svg))
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// Encodes 8 bit RGBA pixels, row by row, as a PNG.
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 8, color type RGBA, default compression, filter and no interlacing.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgba.chunks(width as usize * 4) {
+        // No filter on the row.
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    let data = encoder.finish()?;
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"IDAT", &data);
+    png_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+/// The progress bar as a PNG, for places that don't take SVG. It has no text.
+pub async fn get_progress_png(
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ServerError> {
+    let count = dark_count(&state).await?;
+    let filled = (BAR_WIDTH as f32 * count.percent() / 100.0).round() as u32;
+    let pixels = (0..BAR_HEIGHT)
+        .flat_map(|_| (0..BAR_WIDTH).flat_map(|x| if x < filled { BAR_FILL } else { BAR_BACKGROUND }))
+        .collect::<Vec<u8>>();
+    let png = encode_png(BAR_WIDTH, BAR_HEIGHT, &pixels).map_err(ServerError::Internal)?;
+    Ok(image("image/png", png))
+}
+
+/// A tiny self-contained page with the counter, for iframes. It refreshes itself instead of keeping a connection open.
+pub async fn get_counter(
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ServerError> {
+    let count = dark_count(&state).await?;
+    let (title, icon) = {
+        let site = state.site.read().unwrap();
+        (escape_xml(&site.branding.title), escape_xml(&site.branding.icon))
+    };
+    let html = format!(r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="UTF-8">
+<meta http-equiv="refresh" content="60">
+<title>{title}</title>
+<style>
+body {{ margin: 0; padding: 6px 10px; background: #111; color: #fff; font-family: Verdana, Geneva, sans-serif; font-size: 14px; }}
+a {{ color: #fff; text-decoration: none; }}
+.bar {{ height: 4px; background: #fff2; margin-top: 4px; }}
+.bar div {{ height: 4px; background: #00ffaa; width: {percent:.2}%; }}
+</style>
+</head>
+<body>
+<a href="{base_path}" target="_blank">{icon} <strong>{dark}</strong>/{total} subreddits are dark ({percent:.2}%)</a>
+<div class="bar"><div></div></div>
+</body>
+</html>"##, percent = count.percent(), base_path = escape_xml(&state.base_path), dark = count.dark, total = count.total);
+    Ok(([(header::CACHE_CONTROL, "max-age=30")], Html(html)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::ZlibDecoder;
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(escape_xml(r#"<r/a&b">"#), "&lt;r/a&amp;b&quot;&gt;");
+    }
+
+    /// Splits a PNG into its chunks, checking each one's CRC.
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut rest = png;
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let data = &rest[8..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&kind);
+            hasher.update(data);
+            assert_eq!(hasher.finalize(), crc, "CRC of {}", String::from_utf8_lossy(&kind));
+            chunks.push((kind, data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn encodes_png() {
+        let pixels = [BAR_FILL, BAR_BACKGROUND, BAR_BACKGROUND, BAR_FILL].concat();
+        let png = encode_png(2, 2, &pixels).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let chunks = chunks(&png[8..]);
+        let kinds = chunks.iter().map(|(k, _)| &k[..]).collect::<Vec<&[u8]>>();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+
+        let mut data = Vec::new();
+        ZlibDecoder::new(&chunks[1].1[..]).read_to_end(&mut data).unwrap();
+        let expected = [&[0][..], &BAR_FILL, &BAR_BACKGROUND, &[0], &BAR_BACKGROUND, &BAR_FILL].concat();
+        assert_eq!(data, expected);
+    }
+}
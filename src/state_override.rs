@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+use crate::reddit::id::SubredditId;
+use crate::reddit::SubredditState;
+use crate::redis_helper::RedisHelper;
+
+/// The community maintained list of subreddits running the John Oliver protest.
+pub const DEFAULT_OVERRIDE_LIST: &str = "OLIVER=url:https://raw.githubusercontent.com/username-is-required/reddark-subinfo/main/john-oliver-subs.json";
+
+/// Where an override list is read from.
+#[derive(Clone, Debug)]
+pub enum OverrideSource {
+    Url(String),
+    File(PathBuf),
+    /// A Redis set of subreddit names.
+    Redis(String),
+}
+
+/// An override list as given on the command line: `<STATE>=url:<url>`, `<STATE>=file:<path>` or `<STATE>=redis:<key>`.
+#[derive(Clone, Debug)]
+pub struct OverrideListSpec {
+    pub state: SubredditState,
+    pub source: OverrideSource,
+}
+
+impl OverrideListSpec {
+    pub fn origin(&self) -> String {
+        match &self.source {
+            OverrideSource::Url(url) => format!("url:{url}"),
+            OverrideSource::File(path) => format!("file:{}", path.display()),
+            OverrideSource::Redis(key) => format!("redis:{key}"),
+        }
+    }
+}
+
+impl FromStr for OverrideListSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (state, source) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("Override list must be <STATE>=<kind>:<argument>: {s}"))?;
+        let state = SubredditState::from_str(&state.to_lowercase())?;
+        let (kind, arg) = source.split_once(':').ok_or_else(|| anyhow::anyhow!("Override list must be <STATE>=<kind>:<argument>: {s}"))?;
+        let source = match kind {
+            "url" => OverrideSource::Url(arg.to_string()),
+            "file" => OverrideSource::File(PathBuf::from(arg)),
+            "redis" => OverrideSource::Redis(arg.to_string()),
+            _ => return Err(anyhow::anyhow!("Unknown override list kind: {kind}")),
+        };
+        Ok(OverrideListSpec { state, source })
+    }
+}
+
+/// Problems found in an override list.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// Entries that are not subreddit names.
+    pub invalid: Vec<String>,
+    /// Entries listed more than once.
+    pub duplicates: Vec<SubredditId>,
+    /// Entries that are not on the tracked subreddit list.
+    pub untracked: Vec<SubredditId>,
+    /// Entries that an earlier list already overrides.
+    pub conflicts: Vec<SubredditId>,
+}
+
+/// The current contents of one override list, as stored in Redis and shown by the API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OverrideList {
+    pub state: SubredditState,
+    pub source: String,
+    pub subreddits: Vec<SubredditId>,
+    pub fetched_at: DateTime<Utc>,
+    /// Set when the last fetch failed and these are the last good contents.
+    pub error: Option<String>,
+    pub report: ValidationReport,
+}
+
+fn names_from_json(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(values) => values.iter().flat_map(names_from_json).collect(),
+        // Documents like `{"johnOliverSubs": [...]}` hold their lists in fields.
+        Value::Object(map) => map.values().filter(|v| v.is_array()).flat_map(names_from_json).collect(),
+        _ => vec![],
+    }
+}
+
+fn parse_names(text: &str) -> Vec<String> {
+    match serde_json::from_str::<Value>(text) {
+        Ok(value) => names_from_json(&value),
+        Err(_) => text.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect(),
+    }
+}
+
+/// Fetches override lists, caching them for `refresh` and falling back to their last good contents on errors.
+pub struct StateOverrides {
+    specs: Vec<OverrideListSpec>,
+    refresh: Duration,
+    client: reqwest::Client,
+    cache: Vec<Option<(Instant, OverrideList)>>,
+}
+
+impl StateOverrides {
+    /// `client` fetches the url lists, reusing its connections between refreshes.
+    pub fn new(specs: Vec<OverrideListSpec>, refresh: Duration, client: reqwest::Client) -> Self {
+        let cache = specs.iter().map(|_| None).collect();
+        StateOverrides {
+            specs,
+            refresh,
+            client,
+            cache,
+        }
+    }
+
+    async fn fetch_names(&self, spec: &OverrideListSpec, redis_helper: &RedisHelper) -> Result<Vec<String>> {
+        match &spec.source {
+            OverrideSource::Url(url) => {
+                let text = self.client.get(url).timeout(Duration::from_secs(30)).send().await?.error_for_status()?.text().await?;
+                Ok(parse_names(&text))
+            }
+            OverrideSource::File(path) => {
+                let text = tokio::fs::read_to_string(path).await
+                    .with_context(|| format!("Unable to read {}", path.display()))?;
+                Ok(parse_names(&text))
+            }
+            OverrideSource::Redis(key) => redis_helper.get_set_members(key).await,
+        }
+    }
+
+    /// Returns every list, refreshing the ones whose cache expired.
+    /// The lists are also stored in Redis, so the server can show them and later runs can fall back on them.
+    pub async fn get(&mut self, redis_helper: &RedisHelper, tracked: &[SubredditId]) -> Result<Vec<OverrideList>> {
+        let stored = redis_helper.get_state_overrides().await.unwrap_or_else(|e| {
+            warn!("Unable to load stored override lists: {e}");
+            Vec::new()
+        });
+        let tracked: HashSet<&SubredditId> = tracked.iter().collect();
+
+        for (idx, spec) in self.specs.iter().enumerate() {
+            if let Some((at, _)) = &self.cache[idx] {
+                if at.elapsed() < self.refresh {
+                    continue;
+                }
+            }
+            let origin = spec.origin();
+            let list = match self.fetch_names(spec, redis_helper).await {
+                Ok(names) => {
+                    let mut report = ValidationReport::default();
+                    let mut subreddits: Vec<SubredditId> = Vec::new();
+                    for name in names {
+                        match SubredditId::from_str(&name) {
+                            Ok(id) if subreddits.contains(&id) => report.duplicates.push(id),
+                            Ok(id) => {
+                                if !tracked.contains(&id) {
+                                    report.untracked.push(id.clone());
+                                }
+                                subreddits.push(id);
+                            }
+                            Err(_) => report.invalid.push(name),
+                        }
+                    }
+                    info!("Loaded {} {:?} overrides from {origin}.", subreddits.len(), spec.state);
+                    if !report.invalid.is_empty() || !report.duplicates.is_empty() {
+                        warn!("Override list {origin} has {} invalid and {} duplicate entries.", report.invalid.len(), report.duplicates.len());
+                    }
+                    OverrideList {
+                        state: spec.state,
+                        source: origin,
+                        subreddits,
+                        fetched_at: Utc::now(),
+                        error: None,
+                        report,
+                    }
+                }
+                Err(e) => {
+                    warn!("Unable to fetch override list {origin}, using last good copy: {e}");
+                    let last_good = self.cache[idx].as_ref().map(|(_, l)| l.clone())
+                        .or_else(|| stored.iter().find(|l| l.source == origin && l.state == spec.state).cloned());
+                    let mut list = last_good.unwrap_or_else(|| OverrideList {
+                        state: spec.state,
+                        source: origin,
+                        subreddits: vec![],
+                        fetched_at: Utc::now(),
+                        error: None,
+                        report: ValidationReport::default(),
+                    });
+                    list.error = Some(e.to_string());
+                    list
+                }
+            };
+            self.cache[idx] = Some((Instant::now(), list));
+        }
+
+        // Earlier lists win over later ones.
+        let mut seen: HashSet<SubredditId> = HashSet::new();
+        let mut lists = Vec::new();
+        for (_, list) in self.cache.iter().flatten() {
+            let mut list = list.clone();
+            list.report.conflicts = list.subreddits.iter().filter(|id| seen.contains(*id)).cloned().collect();
+            seen.extend(list.subreddits.iter().cloned());
+            lists.push(list);
+        }
+        redis_helper.set_state_overrides(&lists).await?;
+        Ok(lists)
+    }
+}
+
+/// Maps each overridden subreddit to its state, with earlier lists winning.
+pub fn override_map(lists: &[OverrideList]) -> BTreeMap<SubredditId, SubredditState> {
+    let mut map = BTreeMap::new();
+    for list in lists {
+        for id in list.subreddits.iter() {
+            map.entry(id.clone()).or_insert(list.state);
+        }
+    }
+    map
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A named tracker, following its own subreddit list. Its Redis keys and pub/sub channels are prefixed
+/// with `tracker:<name>:`, and the server shows it under `/t/<name>/`.
+/// Without a tracker, the original unprefixed keys are used and the page is served at `/`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tracker(String);
+
+impl Tracker {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Tracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Tracker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow::anyhow!("Tracker names may only contain letters, digits, - and _: {s}"));
+        }
+        Ok(Tracker(name))
+    }
+}
+
+/// The Redis key or channel `key` of `tracker`.
+pub fn key(tracker: Option<&Tracker>, key: &str) -> String {
+    match tracker {
+        Some(tracker) => format!("tracker:{}:{key}", tracker.0),
+        None => key.to_string(),
+    }
+}
+
+/// Where the server shows `tracker`, with a trailing slash.
+pub fn base_path(tracker: Option<&Tracker>) -> String {
+    match tracker {
+        Some(tracker) => format!("/t/{}/", tracker.0),
+        None => "/".to_string(),
+    }
+}
@@ -1,41 +1,98 @@
+use std::collections::{HashMap, HashSet};
 use std::num::NonZeroU32;
 use std::time::Duration;
 use anyhow::Result;
-use tracing::info;
-use crate::Cli;
+use chrono::Utc;
+use tracing::{info, warn};
+use crate::{admin, Cli};
+use crate::lease::{Lease, UPDATE_LEASE};
+use crate::list_source::{ListSource, ListSourceSpec};
+use crate::reddit::Subreddit;
+use crate::reddit::id::SubredditId;
 use crate::redis_helper::RedisHelper;
+use crate::sections::{apply_size_sections, SizeBucket, SizeSectioning};
 
-pub async fn update_list(cli: &Cli, period: Option<NonZeroU32>) -> Result<()> {
+pub async fn update_list(cli: &Cli, period: Option<NonZeroU32>, sources: &[ListSourceSpec], size_sections: SizeSectioning, size_buckets: &[SizeBucket]) -> Result<()> {
     let reddit = cli.new_reddit_backend().await?;
+    let client = reqwest::Client::builder().build()?;
+    let sources = sources.iter().cloned().map(|s| s.into_source(&client)).collect::<Vec<Box<dyn ListSource>>>();
     let redis_helper = RedisHelper::new(cli).await?;
+    redis_helper.migrate_subreddit_keys().await?;
+    admin::seed_blocklist(&redis_helper).await?;
 
     let mut timer = period.map(|p| tokio::time::interval(Duration::from_secs(p.get() as u64)));
 
     loop {
         info!("Fetching subreddits...");
-        let (sections, subs) = reddit.fetch_subreddits().await?;
-        let existing_subs = redis_helper.get_current_state().await?;
-
-        redis_helper.set_sections(sections).await?;
-
-        for sub in subs {
-            let existing = existing_subs.iter().find(|s| s.name == sub.name);
-
-            if let Some(existing) = existing {
-                if existing.section != sub.section {
-                    info!("Subreddit {} already exists! Updating section to {}...", sub.name, sub.section);
-                    let mut new = existing.clone();
-                    new.section = sub.section.clone();
-                    redis_helper.update_subreddit(&new).await?;
-                } else {
-                    info!("Subreddit {} already exists!", sub.name);
+        let (sections, mut subs) = crate::list_source::fetch_all(&sources, &reddit).await?;
+        let lease = Lease::acquire(&redis_helper, UPDATE_LEASE).await?;
+        lease.hold(async {
+            let existing_subs = redis_helper.get_current_state().await?;
+
+            let blocklist = redis_helper.get_blocklist().await?;
+            subs.retain(|s| {
+                let blocked = blocklist.contains(&s.id());
+                if blocked {
+                    info!("Subreddit {} is blocked, skipping.", s.name);
+                }
+                !blocked
+            });
+
+            let existing_by_id = existing_subs.iter()
+                .map(|s| (s.id(), s))
+                .collect::<HashMap<SubredditId, &Subreddit>>();
+
+            // Sizes come from the metadata the updater stored, so new subreddits are sized on a later run.
+            let sections = apply_size_sections(size_sections, size_buckets, sections, &mut subs, |id| {
+                existing_by_id.get(id).and_then(|s| s.metadata.subscribers)
+            });
+
+            redis_helper.set_sections(sections).await?;
+            redis_helper.set_size_buckets(size_buckets).await?;
+
+            for sub in subs.iter() {
+                if let Some(existing) = existing_by_id.get(&sub.id()) {
+                    if existing.is_withdrawn() {
+                        info!("Subreddit {} is back on the list! Restoring...", sub.name);
+                        update(&redis_helper, existing, |new| {
+                            new.section = sub.section.clone();
+                            new.source = sub.source.clone();
+                            new.withdrawn_at = None;
+                        }).await?;
+                    } else if existing.section != sub.section || existing.source != sub.source {
+                        info!("Subreddit {} already exists! Updating section to {} from {}...", sub.name, sub.section, sub.source);
+                        update(&redis_helper, existing, |new| {
+                            new.section = sub.section.clone();
+                            new.source = sub.source.clone();
+                        }).await?;
+                    } else {
+                        info!("Subreddit {} already exists!", sub.name);
+                    }
+                }  else {
+                    info!("Adding subreddit {}...", sub.name);
+                    if !redis_helper.update_subreddit(sub).await? {
+                        warn!("Subreddit {} was added by someone else meanwhile, leaving it as it is.", sub.name);
+                    }
                 }
-            }  else {
-                info!("Adding subreddit {}...", sub.name);
-                redis_helper.update_subreddit(&sub).await?;
             }
-        }
-        info!("Done!");
+
+            // Tombstone subreddits that left the list. They are kept, with their history, but marked withdrawn.
+            if subs.is_empty() {
+                warn!("Sources returned no subreddits, not withdrawing anything.");
+            } else {
+                let listed = subs.iter().map(|s| s.id()).collect::<HashSet<SubredditId>>();
+                // Blocked subreddits are left as they are, just hidden.
+                for existing in existing_subs.iter().filter(|e| !e.is_withdrawn() && !blocklist.contains(&e.id())) {
+                    if !listed.contains(&existing.id()) {
+                        info!("Subreddit {} is no longer listed! Marking withdrawn...", existing.name);
+                        let withdrawn_at = Utc::now();
+                        update(&redis_helper, existing, |new| new.withdrawn_at = Some(withdrawn_at)).await?;
+                    }
+                }
+            }
+            info!("Done!");
+            anyhow::Ok(())
+        }).await?;
 
         if let Some(t) = timer.as_mut() {
             info!("Awaiting tick...");
@@ -45,4 +102,21 @@ pub async fn update_list(cli: &Cli, period: Option<NonZeroU32>) -> Result<()> {
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+/// Applies `change` to a subreddit and stores it. If someone else changed it since it was read, the change is applied
+/// once more to a fresh copy, and otherwise left for the next run.
+async fn update(redis_helper: &RedisHelper, sub: &Subreddit, change: impl Fn(&mut Subreddit)) -> Result<()> {
+    let mut new = sub.clone();
+    change(&mut new);
+    if redis_helper.update_subreddit(&new).await? {
+        return Ok(());
+    }
+    if let Some(mut fresh) = redis_helper.get_subreddit(&sub.id()).await? {
+        change(&mut fresh);
+        if redis_helper.update_subreddit(&fresh).await? {
+            return Ok(());
+        }
+    }
+    warn!("Subreddit {} keeps changing, leaving it for the next run.", sub.name);
+    Ok(())
+}
@@ -1,74 +1,138 @@
 use std::num::NonZeroU32;
 use std::time::Duration;
 use itertools::Itertools;
-use tracing::{error, info};
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
 use crate::Cli;
+use crate::lease::{Lease, UPDATE_LEASE};
 use crate::reddit::{Subreddit, SubredditDelta, SubredditState};
+use crate::reddit::detect::{detect_protest, SubredditBaseline};
+use crate::reddit::id::SubredditId;
 use crate::redis_helper::RedisHelper;
+use crate::state_override::{override_map, OverrideListSpec, StateOverrides};
 
-pub async fn updater(cli: &Cli, period: Option<NonZeroU32>) -> anyhow::Result<()> {
+pub async fn updater(cli: &Cli, period: Option<NonZeroU32>, withdrawn_grace: chrono::Duration, override_lists: &[OverrideListSpec], override_refresh: Duration, detection: bool, scan_posts: bool) -> anyhow::Result<()> {
     let reddit = cli.new_reddit_backend().await?;
     let redis_helper = RedisHelper::new(cli).await?;
+    redis_helper.migrate_subreddit_keys().await?;
+    let mut state_overrides = StateOverrides::new(override_lists.to_vec(), override_refresh, reqwest::Client::builder().build()?);
 
     let mut timer = period.map(|p| tokio::time::interval(Duration::from_secs(p.get() as u64)));
 
     loop {
-        let start = std::time::Instant::now();
-        let redis_subreddits = redis_helper.get_current_state().await?;
-
-        let oliver_subs = reddit.get_oliver_list().await?;
-        let oliver_subs = oliver_subs.into_iter().map(|s| s.to_lowercase().to_string()).collect::<Vec<String>>();
-
-        // Spawn out all the subreddits.
-        let fns = redis_subreddits.into_iter().chunks(100).into_iter().map(|subreddits| {
-            let reddit = reddit.clone();
-            let redis_helper = redis_helper.clone();
-            let subreddits: Vec<Subreddit> = subreddits.collect();
-            let oliver_subs = oliver_subs.clone();
-
-            let name = subreddits.iter().map(|s| &s.name).join(",");
-
-            let f = async move {
-                let srs: Vec<String> = subreddits.iter().map(|s| s.name.to_string()).collect();
-                info!("Updating subreddits {}...", srs.join(","));
-                let states = reddit.get_subreddit_state_bulk(&srs).await?;
-
-                for prev_state in subreddits.iter() {
-                    let lname = prev_state.name.to_lowercase();
-                    let mut delta = SubredditDelta::from(prev_state.clone());
-                    let state = states.get(&lname).cloned().unwrap_or(SubredditState::UNKNOWN);
-                    delta.subreddit.state = if oliver_subs.contains(&lname) { SubredditState::OLIVER } else { state };
-
-                    if delta.prev_state != delta.subreddit.state {
-                        info!("Change happend! Subreddit {} has gone from {:?} to {:?}.", delta.subreddit.name, delta.prev_state, delta.subreddit.state);
+        // Overlapping runs would work from each other's stale reads.
+        let lease = Lease::acquire(&redis_helper, UPDATE_LEASE).await?;
+        lease.hold(async {
+            let start = std::time::Instant::now();
+            let redis_subreddits = redis_helper.get_current_state().await?;
+            let redis_subreddits = redis_subreddits.into_iter()
+                .filter(|s| !s.withdrawn_for(withdrawn_grace))
+                .collect::<Vec<Subreddit>>();
+
+            let tracked = redis_subreddits.iter().map(|s| s.id()).collect::<Vec<SubredditId>>();
+            let overrides = override_map(&state_overrides.get(&redis_helper, &tracked).await?);
+
+            // Spawn out all the subreddits. The tasks are aborted if the lease is lost.
+            let mut tasks = JoinSet::new();
+            for subreddits in redis_subreddits.into_iter().chunks(100).into_iter() {
+                let reddit = reddit.clone();
+                let redis_helper = redis_helper.clone();
+                let subreddits: Vec<Subreddit> = subreddits.collect();
+                let overrides = overrides.clone();
+
+                let name = subreddits.iter().map(|s| &s.name).join(",");
+
+                let f = async move {
+                    let srs: Vec<SubredditId> = subreddits.iter().map(|s| s.id()).collect();
+                    info!("Updating subreddits {}...", srs.iter().join(","));
+                    let infos = reddit.get_subreddit_state_bulk(&srs).await?;
+
+                    let mut deltas = Vec::with_capacity(subreddits.len());
+                    for prev_state in subreddits.iter() {
+                        let id = prev_state.id();
+                        let mut delta = SubredditDelta::from(prev_state.clone());
+                        let info = infos.get(&id);
+                        let mut state = info.map(|i| i.state).unwrap_or(SubredditState::UNKNOWN);
+                        if let Some(info) = info {
+                            if let Some(fullname) = info.fullname.clone() {
+                                delta.subreddit.fullname = Some(fullname);
+                            }
+                            delta.subreddit.metadata.update(&info.about);
+                        }
+
+                        // Look for protests that leave the subreddit public.
+                        delta.subreddit.detected = None;
+                        if let Some(info) = info.filter(|_| detection && state == SubredditState::PUBLIC) {
+                            let titles = if scan_posts {
+                                reddit.get_recent_post_titles(&id, 25).await.unwrap_or_else(|e| {
+                                    warn!("Unable to fetch recent posts of {id}: {e}");
+                                    Vec::new()
+                                })
+                            } else {
+                                Vec::new()
+                            };
+                            match detect_protest(&info.about, prev_state.baseline.as_ref(), &titles) {
+                                Some(detected) => {
+                                    info!("Detected {:?} protest on {}: {}", detected.state, delta.subreddit.name, detected.reason);
+                                    state = detected.state;
+                                    delta.subreddit.detected = Some(detected.reason);
+                                }
+                                None => {
+                                    delta.subreddit.baseline = Some(SubredditBaseline::from(&info.about));
+                                }
+                            }
+                        }
+
+                        // Override lists win over what reddit says, and admin pins win over everything.
+                        let mut state = overrides.get(&id).copied().unwrap_or(state);
+                        if delta.subreddit.annotations.pin.as_ref().is_some_and(|p| p.is_expired()) {
+                            info!("Pin on {} expired.", delta.subreddit.name);
+                            delta.subreddit.annotations.pin = None;
+                        }
+                        if let Some(pin) = delta.subreddit.annotations.pin.as_ref() {
+                            state = pin.state;
+                            delta.actor = Some(pin.author.clone());
+                            delta.reason = Some(pin.reason.clone());
+                        }
+                        delta.subreddit.state = state;
+
+                        if delta.prev_state != delta.subreddit.state {
+                            info!("Change happend! Subreddit {} has gone from {:?} to {:?}.", delta.subreddit.name, delta.prev_state, delta.subreddit.state);
+                        }
+
+                        deltas.push(delta);
                     }
+                    // The whole chunk goes to Redis in one round trip. Subreddits changed since they were read are skipped.
+                    redis_helper.apply_deltas(&deltas).await?;
 
-                    redis_helper.apply_delta(&delta).await?;
-                }
-
-                anyhow::Ok(())
-            };
+                    anyhow::Ok(())
+                };
 
-            (name, tokio::spawn(f))
-        }).collect::<Vec<_>>();
+                tasks.spawn(async move { (name, f.await) });
+            }
 
-        // Wait for parallel work to finish.
-        let mut failed_subs = 0usize;
-        let total_subs = fns.len();
-        for (n, h) in fns {
-            let result = h.await?;
-            if let Err(e) = result {
-                error!("Failed to update sub {n}: {e}");
-                failed_subs += 1;
+            // Wait for parallel work to finish.
+            let mut failed_subs = 0usize;
+            let total_subs = tasks.len();
+            while let Some(joined) = tasks.join_next().await {
+                let (n, result) = joined?;
+                if let Err(e) = result {
+                    error!("Failed to update sub {n}: {e}");
+                    failed_subs += 1;
+                }
             }
-        }
 
-        redis_helper.trim_history().await?;
+            redis_helper.trim_history().await?;
 
-        let stop = std::time::Instant::now();
-        let taken = stop.duration_since(start);
-        let perc = (((total_subs - failed_subs) as f32) / (total_subs as f32)) * 100.0;
-        info!("Done! Update took {} seconds. {failed_subs} out of {total_subs} subs failed to fetch. Success rate is: {perc:.2}%", taken.as_secs_f32());
+            let stop = std::time::Instant::now();
+            let taken = stop.duration_since(start);
+            let perc = (((total_subs - failed_subs) as f32) / (total_subs as f32)) * 100.0;
+            info!("Done! Update took {} seconds. {failed_subs} out of {total_subs} subs failed to fetch. Success rate is: {perc:.2}%", taken.as_secs_f32());
+            if let Some(status) = reddit.backend_status().await {
+                info!("Backend status: {status}");
+            }
+            anyhow::Ok(())
+        }).await?;
 
         if let Some(t) = timer.as_mut() {
             info!("Awaiting tick...");